@@ -1,7 +1,9 @@
 #![no_main]
 #![no_std]
+use core::fmt::Write;
+use embedded_hal::digital::v2::OutputPin;
 use msp430_rt::entry;
-use msp430fr2355_quickstart::{clocks::*, gpio::*, serial::*, timer::*, watchdog::*};
+use msp430fr2355_quickstart::{clocks::*, gpio::*, serial::*, time::*, timer::*, watchdog::*};
 use panic_msp430 as _;
 
 #[entry]
@@ -12,26 +14,26 @@ fn main() -> ! {
 
     let pmm = periph.PMM.freeze();
 
-    let p1 = periph.P1;
+    let p1 = periph.P1.constrain().unlock(&pmm).split();
     //P1.6 to TB0.CC1A
-    p1.p1sel1.write(|w| unsafe { w.bits(1 << 6) });
-    p1.p1dir.write(|w| unsafe { w.bits(1) });
-    p1.p1out.write(|w| unsafe { w.bits(0) });
+    let cap_pin = p1.p1_6.to_input(&p1.pdir).alternate2(&p1.psel);
+    let mut led = p1.p1_0.to_output(&p1.pdir).enable(&p1.pout);
+    led.set_low().ok();
 
     //For UART
-    periph
-        .P4
-        .p4sel0
-        .write(|w| unsafe { w.bits((1 << 3) | (1 << 2)) });
+    let p4 = periph.P4.constrain().unlock(&pmm).split();
+    let tx_pin = p4.p4_3.alternate1(&p4.psel);
+    let rx_pin = p4.p4_2.alternate1(&p4.psel);
 
+    let mut fram = periph.FRCTL.constrain();
     let (_mclk, smclk, aclk) = periph
         .CS
         .constrain()
-        .mclk_dcoclk(1_000_000)
+        .mclk_dcoclk(1.mhz())
         .unwrap()
         .smclk_divide_1()
         .aclk_refoclk()
-        .freeze();
+        .freeze(&mut fram);
 
     let (mut tx, rx) = periph
         .E_USCI_A1
@@ -40,9 +42,10 @@ fn main() -> ! {
         .lsb_first()
         .stopbits_1()
         .parity_none()
-        .baudrate_smclk(9600, &smclk)
+        .baudrate_smclk(9600.bps(), &smclk)
         .unwrap()
-        .freeze();
+        .freeze(tx_pin, rx_pin)
+        .unwrap();
 
     let mut captures = periph
         .TB0
@@ -51,65 +54,24 @@ fn main() -> ! {
         .set_div(TimerDiv::_2)
         .set_div_ex(TimerDivEx::_3)
         .config_capture()
-        .config_chan1(CaptureMode::Falling, CaptureSelect::CapInputA)
+        .config_chan1_pin(CaptureMode::Falling, cap_pin)
         .freeze();
 
-    p1.p1out.write(|w| unsafe { w.bits(1) });
-    write(&mut tx, 'x');
-    write(&mut tx, 'x');
-    write(&mut tx, 'x');
-    write(&mut tx, 'x');
+    led.set_high().ok();
+    tx.write_str("xxxx").unwrap();
 
     let mut last_cap = 0;
     loop {
         match captures.capture1.capture() {
             Err(_) => {
-                write(&mut tx, '!');
-                write(&mut tx, '\n');
+                tx.write_str("!\n").unwrap();
             }
             Ok(Some(cap)) => {
                 let diff = cap.wrapping_sub(last_cap);
                 last_cap = cap;
-                print_num(&mut tx, diff);
+                writeln!(tx, "{:#06x}", diff).unwrap();
             }
             Ok(None) => {}
         }
     }
 }
-
-fn print_num(tx: &mut Tx, num: u16) {
-    write(tx, '0');
-    write(tx, 'x');
-    print_hex(tx, num >> 12);
-    print_hex(tx, (num >> 8) & 0xF);
-    print_hex(tx, (num >> 4) & 0xF);
-    print_hex(tx, num & 0xF);
-    write(tx, '\n');
-}
-
-fn print_hex(tx: &mut Tx, h: u16) {
-    let c = match h {
-        0 => '0',
-        1 => '1',
-        2 => '2',
-        3 => '3',
-        4 => '4',
-        5 => '5',
-        6 => '6',
-        7 => '7',
-        8 => '8',
-        9 => '9',
-        10 => 'a',
-        11 => 'b',
-        12 => 'c',
-        13 => 'd',
-        14 => 'e',
-        15 => 'f',
-        _ => '?',
-    };
-    write(tx, c);
-}
-
-fn write(tx: &mut Tx, ch: char) {
-    while let Err(_) = tx.write(ch as u8) {}
-}
@@ -53,12 +53,22 @@ fn main() -> ! {
     let batch = p1.split().batch();
     let p1_0 = batch.p1_0.to_input().to_output().on();
     let p1_1 = batch.p1_1.to_input().to_output().on();
-    let parts = BatchParts { p1_0, p1_1 }.write();
+    let parts = P1BatchParts {
+        p1_0,
+        p1_1,
+        p1_2: batch.p1_2,
+        p1_3: batch.p1_3,
+        p1_4: batch.p1_4,
+        p1_5: batch.p1_5,
+        p1_6: batch.p1_6,
+        p1_7: batch.p1_7,
+    }
+    .write();
     //delay(TIME);
     //let mut p1_0 = parts.p1_0.enable(&parts.pout);
-    //p1_0.clear_bit();
+    //p1_0.set_low().unwrap();
     //delay(TIME);
-    //p1_0.set_bit();
+    //p1_0.set_high().unwrap();
 
     loop {}
 }
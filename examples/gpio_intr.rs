@@ -0,0 +1,69 @@
+#![no_main]
+#![no_std]
+#![feature(abi_msp430_interrupt)]
+
+extern crate panic_msp430;
+
+use core::cell::Cell;
+use embedded_hal::digital::v2::ToggleableOutputPin;
+use msp430::interrupt as mspint;
+use msp430_rt::entry;
+use msp430fr2355::interrupt;
+use msp430fr2355_quickstart::{gpio::*, watchdog::*};
+
+static P2_INTR: P2Dispatcher = P2Dispatcher::new();
+static P4_INTR: P4Dispatcher = P4Dispatcher::new();
+
+static RED: mspint::Mutex<Cell<bool>> = mspint::Mutex::new(Cell::new(false));
+static GREEN: mspint::Mutex<Cell<bool>> = mspint::Mutex::new(Cell::new(false));
+
+#[entry]
+fn main() -> ! {
+    let periph = msp430fr2355::Peripherals::take().unwrap();
+
+    let _wdt = periph.WDT_A.constrain();
+
+    let pmm = periph.PMM.freeze();
+
+    let p1 = periph.P1.constrain().unlock(&pmm).split();
+    let mut red = p1.p1_0.to_output(&p1.pdir).enable(&p1.pout);
+    let p6 = periph.P6.constrain().unlock(&pmm).split();
+    let mut green = p6.p6_6.to_output(&p6.pdir).enable(&p6.pout);
+
+    // Both buttons on the LaunchPad pull their pins low when pressed
+    let p2 = periph.P2.constrain().unlock(&pmm).split();
+    let s2 = p2
+        .p2_3
+        .to_input(&p2.pdir)
+        .pullup(&p2.pren)
+        .enable_intr_falling_edge();
+    let p4 = periph.P4.constrain().unlock(&pmm).split();
+    let s1 = p4
+        .p4_1
+        .to_input(&p4.pdir)
+        .pullup(&p4.pren)
+        .enable_intr_falling_edge();
+
+    P2_INTR.register(&s2, || mspint::free(|cs| GREEN.borrow(cs).set(true)));
+    P4_INTR.register(&s1, || mspint::free(|cs| RED.borrow(cs).set(true)));
+    unsafe { mspint::enable() };
+
+    loop {
+        if mspint::free(|cs| RED.borrow(cs).replace(false)) {
+            red.toggle().ok();
+        }
+        if mspint::free(|cs| GREEN.borrow(cs).replace(false)) {
+            green.toggle().ok();
+        }
+    }
+}
+
+#[interrupt]
+fn PORT2() {
+    P2_INTR.dispatch();
+}
+
+#[interrupt]
+fn PORT4() {
+    P4_INTR.dispatch();
+}
@@ -2,7 +2,7 @@
 #![no_std]
 use msp430_rt::entry;
 use msp430fr2355::Peripherals;
-use msp430fr2355_quickstart::{clocks::*, gpio::*, timer::*, watchdog::*};
+use msp430fr2355_quickstart::{clocks::*, gpio::*, time::*, timer::*, watchdog::*};
 use panic_msp430 as _;
 
 #[entry]
@@ -13,22 +13,26 @@ fn main() -> ! {
 
     let pmm = periph.PMM.freeze();
 
-    let p1 = periph.P1;
-    p1.p1dir.write(|w| unsafe { w.bits(0xFF) });
+    let p1 = periph.P1.constrain().unlock(&pmm).split();
     //P1.6 to TB0.1 and P1.7 to TB0.2
-    p1.p1sel1.write(|w| unsafe { w.bits(1 << 6 | 1 << 7) });
-    p1.p1out.write(|w| unsafe { w.bits(0x0) });
+    let pwm1_pin = p1.p1_6.to_output(&p1.pdir).alternate2(&p1.psel);
+    let pwm2_pin = p1.p1_7.to_output(&p1.pdir).alternate2(&p1.psel);
 
+    let mut fram = periph.FRCTL.constrain();
     let (_mclk, smclk, _aclk) = periph
         .CS
         .constrain()
-        .mclk_dcoclk(1_000_000)
+        .mclk_dcoclk(1.mhz())
         .unwrap()
         .smclk_divide_1()
         .aclk_vloclk()
-        .freeze();
+        .freeze(&mut fram);
 
-    let mut pwms = periph.TB0.constrain().use_smclk(&smclk).to_pwm();
+    let mut pwms = periph
+        .TB0
+        .constrain()
+        .use_smclk(&smclk)
+        .to_pwm(pwm1_pin, pwm2_pin);
 
     pwms.set_period(1000);
     pwms.pwm1.set_duty(100);
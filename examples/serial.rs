@@ -3,8 +3,10 @@
 
 extern crate panic_msp430;
 
+use embedded_hal::serial::{Read, Write};
 use msp430_rt::entry;
-use msp430fr2355_quickstart::{clocks::*, gpio::*, serial::*, watchdog::*};
+use msp430fr2355_quickstart::{clocks::*, gpio::*, serial::*, time::*, watchdog::*};
+use nb::block;
 
 #[entry]
 fn main() -> ! {
@@ -13,44 +15,40 @@ fn main() -> ! {
     let wdt = periph.WDT_A.constrain();
 
     let pmm = periph.PMM.freeze();
-    // Should be part of HAL API
-    periph
-        .P4
-        .p4sel0
-        .write(|w| unsafe { w.bits((1 << 3) | (1 << 2)) });
+    let p4 = periph.P4.constrain().unlock(&pmm).split();
+    let tx_pin = p4.p4_3.alternate1(&p4.psel);
+    let rx_pin = p4.p4_2.alternate1(&p4.psel);
 
     let mut p1 = periph.P1.constrain().to_output().unlock(&pmm);
     p1.write(0x00);
 
+    let mut fram = periph.FRCTL.constrain();
     let (_mclk, smclk, aclk) = periph
         .CS
         .constrain()
-        .mclk_dcoclk(2_000_000)
+        .mclk_dcoclk(2.mhz())
         .unwrap()
         .smclk_divide_1()
         .aclk_refoclk()
-        .freeze();
+        .freeze(&mut fram);
 
-    let (mut tx, rx) = periph
+    let (mut tx, mut rx) = periph
         .E_USCI_A1
         .constrain()
         .char_8bits()
         .lsb_first()
         .stopbits_1()
         .parity_none()
-        .baudrate_smclk(9600, &smclk)
+        .baudrate_smclk(9600.bps(), &smclk)
         .unwrap()
-        .freeze();
+        .freeze(tx_pin, rx_pin)
+        .unwrap();
 
     p1.write(0x1);
 
     // Echo loop
     loop {
-        let ch = loop {
-            if let Ok(ch) = rx.read() {
-                break ch;
-            }
-        };
-        while let Err(_) = tx.write(ch) {}
+        let ch = block!(rx.read()).unwrap();
+        block!(tx.write(ch)).unwrap();
     }
 }
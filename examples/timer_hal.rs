@@ -2,9 +2,17 @@
 #![no_std]
 use msp430_rt::entry;
 use msp430fr2355::Peripherals;
-use msp430fr2355_quickstart::{clocks::*, gpio::*, timer::*, watchdog::*};
+use msp430fr2355_quickstart::{clocks::*, gpio::*, time::*, timer::*, watchdog::*};
 use panic_msp430 as _;
 
+// Checked at compile time, so a bad frequency fails the build instead of panicking on the device.
+// Faster MCLKs work as well, since freezing the clocks sets the FRAM wait states (NWAITS) to suit.
+const CLOCKS: ClocksConfig<SmclkDefined, ()> =
+    match ClocksConfig::new().mclk_dcoclk(Hertz(1_000_000)) {
+        Ok(conf) => conf.smclk_divide_1().aclk_vloclk(),
+        Err(_) => panic!("MCLK out of range"),
+    };
+
 #[entry]
 fn main() -> ! {
     let periph = msp430fr2355::Peripherals::take().unwrap();
@@ -15,25 +23,21 @@ fn main() -> ! {
     let mut p1 = periph.P1.constrain().to_output().unlock(&pmm);
     p1.write(0x00);
 
-    let (_mclk, _smclk, aclk) = periph
-        .CS
-        .constrain()
-        // Anything above this and flashing becomes a problem
-        .mclk_dcoclk(1_000_000)
-        .unwrap()
-        .smclk_divide_1()
-        .aclk_vloclk()
-        .freeze();
-
-    let mut timers = periph
-        .TB0
+    let mut fram = periph.FRCTL.constrain();
+    let mut clocks = periph.CS.constrain_with(CLOCKS).freeze_clocks(&mut fram);
+
+    // The VLO can be well off 10 kHz, so measure it before timing anything off ACLK
+    let mut tb0 = periph.TB0;
+    clocks.calibrate_aclk(&mut tb0).unwrap();
+
+    let mut timers = tb0
         .constrain()
-        .use_aclk(&aclk)
+        .use_aclk(clocks.aclk())
         .set_div(TimerDiv::_2)
         .set_div_ex(TimerDivEx::_3)
         .to_periodic();
-    timers.sub_timer1.set_count(200);
-    timers.timer.start(300);
+    timers.sub_timer1.set_count_time(120.ms()).unwrap();
+    timers.timer.start_time(180.ms()).unwrap();
 
     loop {
         while timers.sub_timer1.wait().is_none() {}
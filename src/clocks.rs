@@ -1,36 +1,220 @@
+use crate::gpio::{Alternate1, Alternate2, Output, Unlocked, P1_1, P2_6, P2_7, P3_0, P3_4};
+use crate::regs::Regs;
+use crate::time::{Hertz, Microseconds};
+use crate::timer::{CaptureMode, CaptureSelect};
+use core::convert::TryInto;
 use msp430fr2355 as pac;
 use pac::cs::csctl1::DCORSEL_A;
+use pac::cs::csctl3::SELREF_A;
 use pac::cs::csctl4::{SELA_A, SELMS_A};
+use pac::tb0::tb0ctl::TBSSEL_A;
+
+pub use pac::cs::csctl6::XT1DRIVE_A as Xt1Drive;
 
 pub const REFOCLK: u16 = 32768;
 pub const VLOCLK: u16 = 10000;
+pub const XT1CLK: u16 = 32768;
 pub const DCOCLK_MAX: u32 = REFOCLK as u32 * 768;
+// Range of an external clock driving XT1 in low frequency bypass mode
+pub const XT1_BYPASS_MIN: u32 = 10_000;
+pub const XT1_BYPASS_MAX: u32 = 50_000;
+// Typical MODOSC frequency. The datasheet only guarantees 3.8 MHz to 5.8 MHz.
+pub const MODCLK: u32 = 4_800_000;
 
 const MCLK_DIV_EXP: u8 = 7;
-const MAX_DCO_MUL_EXP: u8 = 10;
+const SMCLK_DIV_EXP: u8 = 3;
+// FLLN is a 10 bit field
+const FLLN_MAX: u32 = 0x3FF;
+
+// FRAM needs a wait state for every 8 MHz of MCLK
+const FRAM_WAIT_FREQ: u32 = 8_000_000;
+const FRCTL_PASSWORD: u8 = 0xA5;
+
+macro_rules! mk_clkconf {
+    ($conf:expr, $mode:expr) => {
+        mk_clkconf!($conf, $mode, $conf.periph)
+    };
+    ($conf:expr, $mode:expr, $periph:expr) => {
+        ClocksConfig {
+            periph: $periph,
+            mclk_sel: $conf.mclk_sel,
+            mclk_div: $conf.mclk_div,
+            mclk_freq: $conf.mclk_freq,
+            aclk_sel: $conf.aclk_sel,
+            fll_ref: $conf.fll_ref,
+            xt1: $conf.xt1,
+            dco_trim: $conf.dco_trim,
+            modclk: $conf.modclk,
+            mode: $mode,
+        }
+    };
+}
+
+// Stands in for the CS peripheral in a configuration that came from constrain. Configurations
+// without it (the () kind) can be built in const items, but can't be frozen.
+#[derive(Clone, Copy)]
+pub struct Cs(());
+
+impl Regs for Cs {
+    type Block = pac::cs::RegisterBlock;
+
+    unsafe fn steal<'a>() -> &'a Self::Block {
+        pac::CS::steal()
+    }
+}
 
 pub trait CsExt {
     fn constrain(self) -> ClocksConfig<Undefined>;
+    // Takes over a configuration built without the peripheral, such as a const item
+    fn constrain_with<MODE>(self, conf: ClocksConfig<MODE, ()>) -> ClocksConfig<MODE>;
 }
 
 impl CsExt for pac::CS {
     fn constrain(self) -> ClocksConfig<Undefined> {
+        self.constrain_with(ClocksConfig::new())
+    }
+
+    fn constrain_with<MODE>(self, conf: ClocksConfig<MODE, ()>) -> ClocksConfig<MODE> {
+        conf.with_periph(Cs(()))
+    }
+}
+
+impl ClocksConfig<Undefined, ()> {
+    // Configuration without the CS peripheral, so it can be built and checked in a const item
+    pub const fn new() -> Self {
         // These are the microcontroller default settings
         ClocksConfig {
-            periph: self,
+            periph: (),
             mode: Undefined,
             mclk_freq: REFOCLK as u32,
             mclk_div: 0,
             mclk_sel: MclkSel::Refoclk,
             aclk_sel: AclkSel::Refoclk,
+            fll_ref: FllRef::Refoclk,
+            xt1: None,
+            dco_trim: TrimSel::Factory,
+            modclk: false,
+        }
+    }
+}
+
+impl Default for ClocksConfig<Undefined, ()> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<MODE> ClocksConfig<MODE, ()> {
+    fn with_periph(self, periph: Cs) -> ClocksConfig<MODE> {
+        mk_clkconf!(self, self.mode, periph)
+    }
+}
+
+// Low frequency XT1, either driving a watch crystal on P2.6/P2.7 or bypassed by an external
+// clock on P2.7
+#[derive(Clone, Copy)]
+pub struct Xt1 {
+    freq: u32,
+    drive: Xt1Drive,
+    bypass: bool,
+}
+
+impl Xt1 {
+    pub fn crystal<DIN, DOUT>(
+        _xin: P2_7<Alternate2<DIN>, Unlocked>,
+        _xout: P2_6<Alternate2<DOUT>, Unlocked>,
+        drive: Xt1Drive,
+    ) -> Self {
+        Xt1 {
+            freq: XT1CLK as u32,
+            drive,
+            bypass: false,
+        }
+    }
+
+    pub fn bypass<DIN, F: TryInto<Hertz>>(
+        _xin: P2_7<Alternate2<DIN>, Unlocked>,
+        freq: F,
+    ) -> Result<Self, ClockFreqError> {
+        let freq = to_hz(freq)?;
+        if freq > XT1_BYPASS_MAX {
+            Err(ClockFreqError::TooHigh)
+        } else if freq < XT1_BYPASS_MIN {
+            Err(ClockFreqError::TooLow)
+        } else {
+            Ok(Xt1 {
+                freq,
+                drive: Xt1Drive::XT1DRIVE_0,
+                bypass: true,
+            })
+        }
+    }
+
+    pub fn freq(&self) -> Hertz {
+        Hertz(self.freq)
+    }
+
+    fn configure(&self) {
+        let cs = unsafe { pac::CS::steal() };
+        let sfr = unsafe { pac::SFR::steal() };
+        cs.csctl6.modify(|_, w| {
+            w.xts()
+                .xts_0()
+                .xt1bypass()
+                .bit(self.bypass)
+                .xt1drive()
+                .variant(self.drive)
+        });
+
+        // Fault flags stay set until the oscillator has started up
+        loop {
+            cs.csctl7
+                .modify(|_, w| w.xt1offg().clear_bit().dcoffg().clear_bit());
+            sfr.sfrifg1.modify(|_, w| w.ofifg().clear_bit());
+            if sfr.sfrifg1.read().ofifg().bit_is_clear() {
+                break;
+            }
         }
     }
 }
 
+pub trait FramExt {
+    fn constrain(self) -> Fram;
+}
+
+impl FramExt for pac::FRCTL {
+    fn constrain(self) -> Fram {
+        Fram(())
+    }
+}
+
+// Token for the FRAM controller, whose wait states have to follow MCLK
+pub struct Fram(());
+
+impl Fram {
+    fn wait_states(&self) -> u8 {
+        unsafe { pac::FRCTL::steal() }.frctl0.read().nwaits().bits()
+    }
+
+    fn set_wait_states(&mut self, nwaits: u8) {
+        unsafe { pac::FRCTL::steal() }.frctl0.write(|w| {
+            unsafe { w.frctlpw().bits(FRCTL_PASSWORD) }
+                .nwaits()
+                .bits(nwaits)
+        });
+    }
+}
+
+fn fram_wait_states(mclk_freq: u32) -> u8 {
+    (mclk_freq.saturating_sub(1) / FRAM_WAIT_FREQ) as u8
+}
+
 enum MclkSel {
     Refoclk,
     Vloclk,
-    Dcoclk { multiplier: u16, range: DCORSEL_A },
+    Xt1,
+    // FLLN is one less than the multiplier: DCOCLKDIV = (FLLN + 1) * FLLREFCLK
+    Dcoclk { flln: u16, range: DCORSEL_A },
 }
 
 impl MclkSel {
@@ -38,36 +222,25 @@ impl MclkSel {
         match self {
             MclkSel::Refoclk => SELMS_A::REFOCLK,
             MclkSel::Vloclk => SELMS_A::VLOCLK,
-            MclkSel::Dcoclk {
-                multiplier: _,
-                range: _,
-            } => SELMS_A::DCOCLKDIV,
+            MclkSel::Xt1 => SELMS_A::XT1CLK,
+            MclkSel::Dcoclk { flln: _, range: _ } => SELMS_A::DCOCLKDIV,
         }
     }
 }
 
-pub struct ClocksConfig<MODE> {
-    periph: pac::CS,
+pub struct ClocksConfig<MODE, CS = Cs> {
+    periph: CS,
     mclk_sel: MclkSel,
     mclk_div: u8,
     mclk_freq: u32,
     aclk_sel: AclkSel,
+    fll_ref: FllRef,
+    xt1: Option<Xt1>,
+    dco_trim: TrimSel,
+    modclk: bool,
     mode: MODE,
 }
 
-macro_rules! mk_clkconf {
-    ($conf:expr, $mode:expr) => {
-        ClocksConfig {
-            periph: $conf.periph,
-            mclk_sel: $conf.mclk_sel,
-            mclk_div: $conf.mclk_div,
-            mclk_freq: $conf.mclk_freq,
-            aclk_sel: $conf.aclk_sel,
-            mode: $mode,
-        }
-    };
-}
-
 // Makes sure MCLK is only defined before SMCLK
 pub struct Undefined;
 pub struct MclkDefined;
@@ -75,39 +248,92 @@ pub struct SmclkDefined(u8);
 pub struct SmclkDisabled;
 
 pub trait SmclkState {
+    type Clock;
+
     fn div(&self) -> Option<u8>;
+    fn clock(&self, mclk_freq: u32) -> Self::Clock;
 }
 
 impl SmclkState for SmclkDefined {
+    type Clock = Smclk;
+
     fn div(&self) -> Option<u8> {
         Some(self.0)
     }
+
+    fn clock(&self, mclk_freq: u32) -> Smclk {
+        Smclk(mclk_freq >> self.0)
+    }
 }
 
 impl SmclkState for SmclkDisabled {
+    type Clock = ();
+
     fn div(&self) -> Option<u8> {
         None
     }
+
+    fn clock(&self, _mclk_freq: u32) {}
 }
 
 #[derive(Clone, Copy)]
 enum AclkSel {
     Vloclk,
     Refoclk,
+    Xt1(u32),
 }
 
 impl AclkSel {
-    fn to_sela(self) -> SELA_A {
+    const fn to_sela(self) -> SELA_A {
         match self {
             AclkSel::Vloclk => SELA_A::VLOCLK,
             AclkSel::Refoclk => SELA_A::REFOCLK,
+            AclkSel::Xt1(_) => SELA_A::XT1CLK,
+        }
+    }
+
+    const fn freq(self) -> u32 {
+        match self {
+            AclkSel::Vloclk => VLOCLK as u32,
+            AclkSel::Refoclk => REFOCLK as u32,
+            AclkSel::Xt1(freq) => freq,
         }
     }
+}
+
+// DCOFTRIM and DCOTAP found by the software trim. Only valid for the DCO frequency and FLL
+// reference it was found with, so cache it alongside those.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DcoTrim {
+    pub ftrim: u8,
+    pub tap: u16,
+}
+
+#[derive(Clone, Copy)]
+enum TrimSel {
+    Factory,
+    Auto,
+    Cached(DcoTrim),
+}
 
-    fn freq(self) -> u16 {
+#[derive(Clone, Copy)]
+enum FllRef {
+    Refoclk,
+    Xt1(u32),
+}
+
+impl FllRef {
+    fn to_selref(self) -> SELREF_A {
         match self {
-            AclkSel::Vloclk => VLOCLK as u16,
-            AclkSel::Refoclk => REFOCLK as u16,
+            FllRef::Refoclk => SELREF_A::REFOCLK,
+            FllRef::Xt1(_) => SELREF_A::XT1CLK,
+        }
+    }
+
+    const fn freq(self) -> u32 {
+        match self {
+            FllRef::Refoclk => REFOCLK as u32,
+            FllRef::Xt1(freq) => freq,
         }
     }
 }
@@ -116,35 +342,55 @@ impl AclkSel {
 pub enum ClockFreqError {
     TooHigh,
     TooLow,
+    // No divider produces the requested frequency exactly
+    Inexact,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CalibrationError {
+    // MCLK ticked less than once per ACLK period, so it's too slow to measure ACLK against
+    RefTooSlow,
+    // An ACLK edge was captured before the previous capture was read
+    CaptureOverflow,
 }
 
-impl<MODE> ClocksConfig<MODE> {
-    fn match_clk_spec(
-        hz: u16,
-        max_freq: u16,
+impl<MODE, CS: Copy> ClocksConfig<MODE, CS> {
+    const fn match_clk_spec(
+        hz: u32,
+        max_freq: u32,
         max_div_exp: u8,
-    ) -> Result<(u16, u8), ClockFreqError> {
+    ) -> Result<(u32, u8), ClockFreqError> {
         if hz > max_freq {
             Err(ClockFreqError::TooHigh)
         } else if hz < max_freq >> max_div_exp {
             Err(ClockFreqError::TooLow)
         } else {
-            for div in (0..max_div_exp + 1).rev() {
+            let mut div = max_div_exp + 1;
+            while div > 0 {
+                div -= 1;
                 let current_freq = max_freq >> div;
                 if hz <= current_freq {
                     let prev_freq = max_freq >> (div + 1);
                     let cur_diff = current_freq - hz;
                     let prev_diff = hz - prev_freq;
 
-                    let clk_div = if cur_diff < prev_diff { div } else { div + 1 } as u8;
+                    let clk_div = if cur_diff < prev_diff { div } else { div + 1 };
                     let clk_freq = max_freq >> clk_div;
                     return Ok((clk_freq, clk_div));
                 }
             }
-            return Ok((max_freq >> max_div_exp, max_div_exp));
+            Ok((max_freq >> max_div_exp, max_div_exp))
         }
     }
 
+    pub const fn mclk(&self) -> Hertz {
+        Hertz(self.mclk_freq)
+    }
+
+    pub const fn aclk(&self) -> Hertz {
+        Hertz(self.aclk_sel.freq())
+    }
+
     pub const fn aclk_refoclk(mut self) -> Self {
         self.aclk_sel = AclkSel::Refoclk;
         self
@@ -154,9 +400,34 @@ impl<MODE> ClocksConfig<MODE> {
         self.aclk_sel = AclkSel::Vloclk;
         self
     }
+
+    // Searches for the DCOFTRIM that centers DCOTAP when the DCO is configured. Takes a few FLL
+    // lock times, so the result can be read back from Mclk and passed to dco_trim next time.
+    pub const fn dco_autotrim(mut self) -> Self {
+        self.dco_trim = TrimSel::Auto;
+        self
+    }
+
+    pub const fn dco_trim(mut self, trim: DcoTrim) -> Self {
+        self.dco_trim = TrimSel::Cached(trim);
+        self
+    }
+
+    // Lets modules that run from MODCLK (ADC, FRAM controller) start MODOSC when they need it.
+    // MODCLK can't drive MCLK, SMCLK or ACLK on this device.
+    pub const fn modclk_request(mut self) -> Self {
+        self.modclk = true;
+        self
+    }
+
+    pub const fn aclk_xt1(mut self, xt1: &Xt1) -> Self {
+        self.aclk_sel = AclkSel::Xt1(xt1.freq);
+        self.xt1 = Some(*xt1);
+        self
+    }
 }
 
-fn determine_dco_range(hz: u32) -> DCORSEL_A {
+const fn determine_dco_range(hz: u32) -> DCORSEL_A {
     let fll_ref = REFOCLK as u32;
     if hz < fll_ref * 32 {
         DCORSEL_A::DCORSEL_0
@@ -177,31 +448,61 @@ fn determine_dco_range(hz: u32) -> DCORSEL_A {
     }
 }
 
-impl ClocksConfig<Undefined> {
-    pub fn mclk_refoclk(self, hz: u16) -> Result<ClocksConfig<MclkDefined>, ClockFreqError> {
-        let (mclk_freq, mclk_div) = Self::match_clk_spec(hz, REFOCLK, MCLK_DIV_EXP)?;
-        let mclk_sel = MclkSel::Refoclk;
-        Ok(ClocksConfig {
-            mclk_div,
-            mclk_freq: mclk_freq as u32,
-            mclk_sel,
-            ..mk_clkconf!(self, MclkDefined)
-        })
-    }
-
-    pub fn mclk_vloclk(self, hz: u16) -> Result<ClocksConfig<MclkDefined>, ClockFreqError> {
-        let (mclk_freq, mclk_div) = Self::match_clk_spec(hz, VLOCLK, MCLK_DIV_EXP)?;
-        let mclk_sel = MclkSel::Vloclk;
-        Ok(ClocksConfig {
-            mclk_div,
-            mclk_freq: mclk_freq as u32,
-            mclk_sel,
-            ..mk_clkconf!(self, MclkDefined)
-        })
-    }
-
-    pub fn mclk_dcoclk(self, hz: u32) -> Result<ClocksConfig<MclkDefined>, ClockFreqError> {
-        let fll_ref = REFOCLK as u32;
+impl<CS: Copy> ClocksConfig<Undefined, CS> {
+    const fn mclk_divided(
+        self,
+        spec: Result<(u32, u8), ClockFreqError>,
+        mclk_sel: MclkSel,
+    ) -> Result<ClocksConfig<MclkDefined, CS>, ClockFreqError> {
+        match spec {
+            Ok((mclk_freq, mclk_div)) => Ok(ClocksConfig {
+                mclk_div,
+                mclk_freq,
+                mclk_sel,
+                ..mk_clkconf!(self, MclkDefined)
+            }),
+            Err(err) => Err(err),
+        }
+    }
+
+    const fn set_mclk_refoclk(
+        self,
+        hz: u32,
+    ) -> Result<ClocksConfig<MclkDefined, CS>, ClockFreqError> {
+        let spec = Self::match_clk_spec(hz, REFOCLK as u32, MCLK_DIV_EXP);
+        self.mclk_divided(spec, MclkSel::Refoclk)
+    }
+
+    const fn set_mclk_vloclk(
+        self,
+        hz: u32,
+    ) -> Result<ClocksConfig<MclkDefined, CS>, ClockFreqError> {
+        let spec = Self::match_clk_spec(hz, VLOCLK as u32, MCLK_DIV_EXP);
+        self.mclk_divided(spec, MclkSel::Vloclk)
+    }
+
+    const fn set_mclk_xt1(
+        mut self,
+        xt1: &Xt1,
+        hz: u32,
+    ) -> Result<ClocksConfig<MclkDefined, CS>, ClockFreqError> {
+        let spec = Self::match_clk_spec(hz, xt1.freq, MCLK_DIV_EXP);
+        self.xt1 = Some(*xt1);
+        self.mclk_divided(spec, MclkSel::Xt1)
+    }
+
+    // Must be picked before the DCO frequency, since the FLL multiplies this reference
+    pub const fn fll_ref_xt1(mut self, xt1: &Xt1) -> Self {
+        self.fll_ref = FllRef::Xt1(xt1.freq);
+        self.xt1 = Some(*xt1);
+        self
+    }
+
+    const fn set_mclk_dcoclk(
+        self,
+        hz: u32,
+    ) -> Result<ClocksConfig<MclkDefined, CS>, ClockFreqError> {
+        let fll_ref = self.fll_ref.freq();
         if hz < fll_ref {
             Err(ClockFreqError::TooLow)
         } else if hz > DCOCLK_MAX {
@@ -212,12 +513,17 @@ impl ClocksConfig<Undefined> {
                 multiplier += 1;
             }
 
+            // Only reachable with a slow XT1 as the FLL reference
+            if multiplier - 1 > FLLN_MAX {
+                return Err(ClockFreqError::TooHigh);
+            }
+
             let mclk_freq = multiplier * fll_ref;
             Ok(ClocksConfig {
                 mclk_div: 0,
                 mclk_freq,
                 mclk_sel: MclkSel::Dcoclk {
-                    multiplier: multiplier as u16,
+                    flln: (multiplier - 1) as u16,
                     range: determine_dco_range(hz),
                 },
                 ..mk_clkconf!(self, MclkDefined)
@@ -225,71 +531,250 @@ impl ClocksConfig<Undefined> {
         }
     }
 
-    pub const fn mclk_default(self) -> ClocksConfig<MclkDefined> {
+    pub const fn mclk_default(self) -> ClocksConfig<MclkDefined, CS> {
         mk_clkconf!(self, MclkDefined)
     }
 }
 
+// The frequency setters come in two flavours. Configurations built without the peripheral take
+// Hertz so they can be used in const items, while the ones from CsExt::constrain take anything
+// that converts to Hertz.
+impl ClocksConfig<Undefined, ()> {
+    pub const fn mclk_refoclk(
+        self,
+        freq: Hertz,
+    ) -> Result<ClocksConfig<MclkDefined, ()>, ClockFreqError> {
+        self.set_mclk_refoclk(freq.0)
+    }
+
+    pub const fn mclk_vloclk(
+        self,
+        freq: Hertz,
+    ) -> Result<ClocksConfig<MclkDefined, ()>, ClockFreqError> {
+        self.set_mclk_vloclk(freq.0)
+    }
+
+    pub const fn mclk_xt1(
+        self,
+        xt1: &Xt1,
+        freq: Hertz,
+    ) -> Result<ClocksConfig<MclkDefined, ()>, ClockFreqError> {
+        self.set_mclk_xt1(xt1, freq.0)
+    }
+
+    pub const fn mclk_dcoclk(
+        self,
+        freq: Hertz,
+    ) -> Result<ClocksConfig<MclkDefined, ()>, ClockFreqError> {
+        self.set_mclk_dcoclk(freq.0)
+    }
+}
+
+impl ClocksConfig<MclkDefined, ()> {
+    pub const fn smclk_freq(
+        self,
+        freq: Hertz,
+    ) -> Result<ClocksConfig<SmclkDefined, ()>, ClockFreqError> {
+        self.set_smclk_freq(freq.0)
+    }
+}
+
+// A frequency that doesn't fit in Hertz is too high for any clock
+fn to_hz<F: TryInto<Hertz>>(freq: F) -> Result<u32, ClockFreqError> {
+    freq.try_into()
+        .map(|hz| hz.0)
+        .map_err(|_| ClockFreqError::TooHigh)
+}
+
+impl ClocksConfig<Undefined> {
+    pub fn mclk_refoclk<F: TryInto<Hertz>>(
+        self,
+        freq: F,
+    ) -> Result<ClocksConfig<MclkDefined>, ClockFreqError> {
+        self.set_mclk_refoclk(to_hz(freq)?)
+    }
+
+    pub fn mclk_vloclk<F: TryInto<Hertz>>(
+        self,
+        freq: F,
+    ) -> Result<ClocksConfig<MclkDefined>, ClockFreqError> {
+        self.set_mclk_vloclk(to_hz(freq)?)
+    }
+
+    pub fn mclk_xt1<F: TryInto<Hertz>>(
+        self,
+        xt1: &Xt1,
+        freq: F,
+    ) -> Result<ClocksConfig<MclkDefined>, ClockFreqError> {
+        self.set_mclk_xt1(xt1, to_hz(freq)?)
+    }
+
+    pub fn mclk_dcoclk<F: TryInto<Hertz>>(
+        self,
+        freq: F,
+    ) -> Result<ClocksConfig<MclkDefined>, ClockFreqError> {
+        self.set_mclk_dcoclk(to_hz(freq)?)
+    }
+}
+
 impl ClocksConfig<MclkDefined> {
-    pub const fn smclk_divide_1(self) -> ClocksConfig<SmclkDefined> {
+    pub fn smclk_freq<F: TryInto<Hertz>>(
+        self,
+        freq: F,
+    ) -> Result<ClocksConfig<SmclkDefined>, ClockFreqError> {
+        self.set_smclk_freq(to_hz(freq)?)
+    }
+}
+
+// SMCLK always runs off the MCLK source and divider, so it can only be a further division of MCLK
+impl<CS: Copy> ClocksConfig<MclkDefined, CS> {
+    const fn set_smclk_freq(
+        self,
+        hz: u32,
+    ) -> Result<ClocksConfig<SmclkDefined, CS>, ClockFreqError> {
+        if hz > self.mclk_freq {
+            return Err(ClockFreqError::TooHigh);
+        } else if hz < self.mclk_freq >> SMCLK_DIV_EXP {
+            return Err(ClockFreqError::TooLow);
+        }
+        let mut div = 0;
+        while div <= SMCLK_DIV_EXP {
+            if hz << div == self.mclk_freq {
+                return Ok(mk_clkconf!(self, SmclkDefined(div)));
+            }
+            div += 1;
+        }
+        Err(ClockFreqError::Inexact)
+    }
+
+    pub const fn smclk_divide_1(self) -> ClocksConfig<SmclkDefined, CS> {
         mk_clkconf!(self, SmclkDefined(0))
     }
 
-    pub const fn smclk_divide_2(self) -> ClocksConfig<SmclkDefined> {
+    pub const fn smclk_divide_2(self) -> ClocksConfig<SmclkDefined, CS> {
         mk_clkconf!(self, SmclkDefined(1))
     }
 
-    pub const fn smclk_divide_4(self) -> ClocksConfig<SmclkDefined> {
+    pub const fn smclk_divide_4(self) -> ClocksConfig<SmclkDefined, CS> {
         mk_clkconf!(self, SmclkDefined(2))
     }
 
-    pub const fn smclk_divide_8(self) -> ClocksConfig<SmclkDefined> {
+    pub const fn smclk_divide_8(self) -> ClocksConfig<SmclkDefined, CS> {
         mk_clkconf!(self, SmclkDefined(3))
     }
 
-    pub const fn smclk_off(self) -> ClocksConfig<SmclkDisabled> {
+    pub const fn smclk_off(self) -> ClocksConfig<SmclkDisabled, CS> {
         mk_clkconf!(self, SmclkDisabled)
     }
 }
 
 impl<SMCLK: SmclkState> ClocksConfig<SMCLK> {
-    fn configure_periph(&self) {
-        if let MclkSel::Dcoclk { multiplier, range } = self.mclk_sel {
+    fn configure_periph(&self, fram: &mut Fram) {
+        // Wait states go up before MCLK speeds up, and only come down once it has slowed down
+        let nwaits = fram_wait_states(self.mclk_freq);
+        if nwaits > fram.wait_states() {
+            fram.set_wait_states(nwaits);
+        }
+
+        // XT1 has to be running before anything is switched over to it
+        if let Some(xt1) = self.xt1 {
+            xt1.configure();
+        }
+
+        if let MclkSel::Dcoclk { flln, range } = self.mclk_sel {
             // Turn off FLL if it were possible
-            self.periph.csctl3.write(|w| w.selref().refoclk());
-            self.periph.csctl0.write(|w| unsafe { w.bits(0) });
-            self.periph.csctl1.write(|w| w.dcorsel().variant(range));
             self.periph
+                .regs()
+                .csctl3
+                .write(|w| w.selref().variant(self.fll_ref.to_selref()));
+            let (tap, ftrim) = match self.dco_trim {
+                TrimSel::Factory => (0, None),
+                TrimSel::Auto => (DCO_TAP_MID, Some(DCO_FTRIM_START)),
+                TrimSel::Cached(trim) => (trim.tap, Some(trim.ftrim)),
+            };
+            self.periph
+                .regs()
+                .csctl0
+                .write(|w| unsafe { w.dco().bits(tap) });
+            self.periph.regs().csctl1.write(|w| {
+                let w = w.dcorsel().variant(range);
+                match ftrim {
+                    Some(ftrim) => unsafe { w.dcoftrimen().set_bit().dcoftrim().bits(ftrim) },
+                    None => w,
+                }
+            });
+            self.periph
+                .regs()
                 .csctl2
-                .write(|w| unsafe { w.flln().bits(multiplier) }.flld()._1());
+                .write(|w| unsafe { w.flln().bits(flln) }.flld()._1());
             // Turn on FLL if it were possible
 
             msp430::asm::nop();
             msp430::asm::nop();
             msp430::asm::nop();
-            while !self.periph.csctl7.read().fllunlock().is_fllunlock_0() {}
+            while !self
+                .periph
+                .regs()
+                .csctl7
+                .read()
+                .fllunlock()
+                .is_fllunlock_0()
+            {}
+
+            if let TrimSel::Auto = self.dco_trim {
+                dco_software_trim(self.periph.regs(), flln);
+            }
         }
 
-        self.periph.csctl4.write(|w| {
+        self.periph.regs().csctl4.write(|w| {
             w.sela()
                 .variant(self.aclk_sel.to_sela())
                 .selms()
                 .variant(self.mclk_sel.selms())
         });
 
-        self.periph.csctl5.write(|w| {
+        // Left alone otherwise, so the reset default and anything set up outside the HAL stick
+        if self.modclk {
+            self.periph
+                .regs()
+                .csctl8
+                .modify(|_, w| w.modoscreqen().set_bit());
+        }
+
+        self.periph.regs().csctl5.write(|w| {
             let w = w.vloautooff().set_bit().divm().bits(self.mclk_div);
             match self.mode.div() {
                 Some(div) => w.divs().bits(div),
                 None => w.smclkoff().set_bit(),
             }
         });
+
+        if nwaits < fram.wait_states() {
+            fram.set_wait_states(nwaits);
+        }
+    }
+
+    // Like freeze, but keeps hold of the CS so the clocks can be changed again later
+    pub fn freeze_clocks(self, fram: &mut Fram) -> Clocks<SMCLK::Clock> {
+        self.configure_periph(fram);
+        Clocks {
+            mclk: Mclk(self.mclk_freq),
+            smclk: self.mode.clock(self.mclk_freq),
+            aclk: Aclk(self.aclk_sel.freq()),
+            periph: self.periph,
+        }
+    }
+}
+
+impl<CS: Copy> ClocksConfig<SmclkDefined, CS> {
+    pub const fn smclk(&self) -> Hertz {
+        Hertz(self.mclk_freq >> self.mode.0)
     }
 }
 
 impl ClocksConfig<SmclkDefined> {
-    pub fn freeze(self) -> (Mclk, Smclk, Aclk) {
-        self.configure_periph();
+    pub fn freeze(self, fram: &mut Fram) -> (Mclk, Smclk, Aclk) {
+        self.configure_periph(fram);
         (
             Mclk(self.mclk_freq),
             Smclk(self.mclk_freq >> self.mode.0),
@@ -299,42 +784,906 @@ impl ClocksConfig<SmclkDefined> {
 }
 
 impl ClocksConfig<SmclkDisabled> {
-    pub fn freeze(self) -> (Mclk, Aclk) {
-        self.configure_periph();
+    pub fn freeze(self, fram: &mut Fram) -> (Mclk, Aclk) {
+        self.configure_periph(fram);
         (Mclk(self.mclk_freq), Aclk(self.aclk_sel.freq()))
     }
 }
 
+const DCO_TAP_MID: u16 = 256;
+const DCO_FTRIM_START: u8 = 3;
+const DCO_FTRIM_MAX: u8 = 7;
+
+// TI's software trim. Steps DCOFTRIM towards whichever side the FLL pushed DCOTAP until the tap
+// crosses the middle of its range, then reloads the tap/trim pair that landed closest to it.
+fn dco_software_trim(cs: &pac::cs::RegisterBlock, flln: u16) {
+    dco_trim_search(cs, || {
+        while cs.csctl7.read().dcoffg().bit_is_set() {
+            cs.csctl7.modify(|_, w| w.dcoffg().clear_bit());
+        }
+        // Give the FLL 24 reference cycles before trusting its lock status
+        for _ in 0..(flln as u32 + 1) * 24 {
+            msp430::asm::nop();
+        }
+        loop {
+            let csctl7 = cs.csctl7.read();
+            if csctl7.fllunlock().is_fllunlock_0() || csctl7.dcoffg().bit_is_set() {
+                break;
+            }
+        }
+    });
+}
+
+// The search itself. settle runs after every DCOFTRIM step and waits for the FLL to move the tap.
+fn dco_trim_search(cs: &pac::cs::RegisterBlock, mut settle: impl FnMut()) {
+    let mut prev_tap = None;
+    let mut best = (u16::MAX, 0, 0);
+    loop {
+        cs.csctl0.write(|w| unsafe { w.dco().bits(DCO_TAP_MID) });
+        settle();
+
+        let csctl0 = cs.csctl0.read();
+        let csctl1 = cs.csctl1.read();
+        let tap = csctl0.dco().bits();
+        let ftrim = csctl1.dcoftrim().bits();
+
+        let (delta, crossed, next_ftrim) = if tap < DCO_TAP_MID {
+            let crossed = prev_tap.map_or(false, |prev| prev >= DCO_TAP_MID);
+            (DCO_TAP_MID - tap, crossed, ftrim.checked_sub(1))
+        } else {
+            let crossed = prev_tap.map_or(false, |prev| prev < DCO_TAP_MID);
+            (
+                tap - DCO_TAP_MID,
+                crossed,
+                Some(ftrim + 1).filter(|&t| t <= DCO_FTRIM_MAX),
+            )
+        };
+        if delta < best.0 {
+            best = (delta, csctl0.bits(), csctl1.bits());
+        }
+
+        match next_ftrim {
+            Some(next) if !crossed => {
+                cs.csctl1.modify(|_, w| unsafe { w.dcoftrim().bits(next) });
+                prev_tap = Some(tap);
+            }
+            _ => break,
+        }
+    }
+
+    cs.csctl0.write(|w| unsafe { w.bits(best.1) });
+    cs.csctl1.write(|w| unsafe { w.bits(best.2) });
+    while !cs.csctl7.read().fllunlock().is_fllunlock_0() {}
+}
+
 pub struct Mclk(u32);
 pub struct Smclk(u32);
-pub struct Aclk(u16);
+pub struct Aclk(u32);
+pub struct Modclk(());
+
+// Clocks that can be reconfigured at runtime. Peripherals whose timing depends on the clocks
+// (baud rates, timer periods) borrow the clock tokens from the handle, which stops the clocks from
+// being changed while any of them are still around.
+pub struct Clocks<SMCLK> {
+    periph: Cs,
+    mclk: Mclk,
+    smclk: SMCLK,
+    aclk: Aclk,
+}
+
+impl<SMCLK> Clocks<SMCLK> {
+    pub fn mclk(&self) -> &Mclk {
+        &self.mclk
+    }
+
+    pub fn aclk(&self) -> &Aclk {
+        &self.aclk
+    }
+
+    // Starts from the reset configuration, same as CsExt::constrain, so XT1 and the DCO trim
+    // have to be set up again if they're still wanted. SMCLK has to stay on or off as it was.
+    // ACLK goes back to its nominal frequency, so calibrate_aclk has to be run again afterwards.
+    pub fn reconfigure<NEW: SmclkState<Clock = SMCLK>>(
+        &mut self,
+        fram: &mut Fram,
+        conf: impl FnOnce(ClocksConfig<Undefined>) -> ClocksConfig<NEW>,
+    ) {
+        *self = conf(ClocksConfig::new().with_periph(self.periph)).freeze_clocks(fram);
+    }
+
+    // Measures ACLK against MCLK, which usually runs off the FLL-locked DCO, by capturing ACLK
+    // edges on TB0, whose CCI0B input is ACLK. SMCLK is run undivided for the measurement, since
+    // that's how MCLK reaches the timer, and put back afterwards. Mostly for the VLO, which can be
+    // far off its nominal 10 kHz. The measured frequency replaces the nominal one, so everything
+    // configured from aclk() afterwards uses it. On error ACLK keeps the frequency it had. Leaves
+    // TB0 stopped.
+    pub fn calibrate_aclk(&mut self, tb0: &mut pac::TB0) -> Result<(), CalibrationError> {
+        let cs = self.periph.regs();
+        let csctl5 = cs.csctl5.read().bits();
+        cs.csctl5
+            .modify(|_, w| w.divs().bits(0).smclkoff().clear_bit());
+
+        let timer = tb0.regs();
+        timer.tb0ctl.write(|w| w.tbclr().set_bit());
+        timer.tb0ex0.write(|w| w.tbidex().bits(0));
+        timer.tb0cctl0.write(|w| {
+            w.cap()
+                .capture()
+                .scs()
+                .sync()
+                .cm()
+                .bits(CaptureMode::Rising as u8)
+                .ccis()
+                .bits(CaptureSelect::CapInputB as u8)
+        });
+        timer
+            .tb0ctl
+            .write(|w| w.tbssel().variant(TBSSEL_A::SMCLK).mc().continuous());
+
+        let capture = || {
+            while timer.tb0cctl0.read().ccifg().bit_is_clear() {}
+            let count = timer.tb0ccr0.read().bits();
+            let overflow = timer.tb0cctl0.read().cov().bit_is_set();
+            timer
+                .tb0cctl0
+                .modify(|_, w| w.ccifg().clear_bit().cov().clear_bit());
+            if overflow {
+                Err(CalibrationError::CaptureOverflow)
+            } else {
+                Ok(count)
+            }
+        };
+        let measure = || {
+            let mut prev = capture()?;
+            let mut ticks = 0;
+            for _ in 0..CALIBRATION_PERIODS {
+                let count = capture()?;
+                ticks += count.wrapping_sub(prev) as u32;
+                prev = count;
+            }
+            measured_freq(self.mclk.0, CALIBRATION_PERIODS, ticks)
+                .ok_or(CalibrationError::RefTooSlow)
+        };
+        let freq = measure();
+
+        timer.tb0ctl.write(|w| w.mc().stop());
+        timer.tb0cctl0.reset();
+
+        cs.csctl5.write(|w| unsafe { w.bits(csctl5) });
+        self.aclk = Aclk(freq?);
+        Ok(())
+    }
+}
+
+impl Clocks<Smclk> {
+    pub fn smclk(&self) -> &Smclk {
+        &self.smclk
+    }
+}
 
 pub trait Clock {
-    type Freq;
+    fn freq(&self) -> Hertz;
 
-    fn freq(&self) -> Self::Freq;
+    // Number of cycles of this clock in the duration
+    fn ticks<D: TryInto<Microseconds>>(&self, dur: D) -> Option<u32> {
+        self.freq().ticks(dur)
+    }
+
+    // Drives the clock out of the pin until the returned guard is released
+    fn output<PIN: ClockOutPin<Self>>(&self, pin: PIN) -> ClockOutput<'_, Self, PIN>
+    where
+        Self: Sized,
+    {
+        ClockOutput { _clk: self, pin }
+    }
 }
 
-impl Clock for Mclk {
-    type Freq = u32;
+// Pins that can output a given clock, once muxed to the right function and set as outputs
+pub trait ClockOutPin<CLK> {}
+
+impl<TOK> ClockOutPin<Mclk> for P3_0<Alternate1<Output<TOK>>, Unlocked> {}
+impl<TOK> ClockOutPin<Smclk> for P3_4<Alternate1<Output<TOK>>, Unlocked> {}
+impl<TOK> ClockOutPin<Aclk> for P1_1<Alternate2<Output<TOK>>, Unlocked> {}
 
-    fn freq(&self) -> u32 {
-        self.0
+// Keeps a pin outputting a clock, and keeps the clock from being reconfigured in the meantime
+pub struct ClockOutput<'c, CLK, PIN> {
+    _clk: &'c CLK,
+    pin: PIN,
+}
+
+impl<'c, CLK, PIN> ClockOutput<'c, CLK, PIN> {
+    pub fn release(self) -> PIN {
+        self.pin
     }
 }
 
-impl Clock for Smclk {
-    type Freq = u32;
+impl Mclk {
+    // Current DCO trim, to be cached after an auto-trim. Meaningless if MCLK isn't from the DCO.
+    pub fn dco_trim(&self) -> DcoTrim {
+        let cs = unsafe { pac::CS::steal() };
+        DcoTrim {
+            ftrim: cs.csctl1.read().dcoftrim().bits(),
+            tap: cs.csctl0.read().dco().bits(),
+        }
+    }
 
-    fn freq(&self) -> u32 {
-        self.0
+    // Only available if MODCLK requests are enabled, such as by modclk_request in the clock
+    // configuration
+    pub fn modclk(&self) -> Option<Modclk> {
+        let cs = unsafe { pac::CS::steal() };
+        if cs.csctl8.read().modoscreqen().bit_is_set() {
+            Some(Modclk(()))
+        } else {
+            None
+        }
+    }
+}
+
+// ACLK periods averaged over by a calibration
+const CALIBRATION_PERIODS: u32 = 32;
+
+// Frequency of a clock that lasted `ticks` cycles of the reference over `periods` of its own
+// cycles. None if the reference didn't tick at all, or ticked less than once per period, since
+// then it's too slow to say anything about the clock.
+fn measured_freq(ref_freq: u32, periods: u32, ticks: u32) -> Option<u32> {
+    if ticks < periods {
+        return None;
+    }
+    let ticks = ticks as u64;
+    Some(((ref_freq as u64 * periods as u64 + ticks / 2) / ticks) as u32)
+}
+
+impl Clock for Modclk {
+    fn freq(&self) -> Hertz {
+        Hertz(MODCLK)
+    }
+}
+
+impl Clock for Mclk {
+    fn freq(&self) -> Hertz {
+        Hertz(self.0)
+    }
+}
+
+impl Clock for Smclk {
+    fn freq(&self) -> Hertz {
+        Hertz(self.0)
     }
 }
 
 impl Clock for Aclk {
-    type Freq = u16;
+    fn freq(&self) -> Hertz {
+        Hertz(self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClockFaults {
+    // Latched: XT1 has stopped oscillating since the flag was last cleared
+    pub xt1: bool,
+    // Latched: the DCO has hit the end of its tap range since the flag was last cleared
+    pub dco: bool,
+    // Active: the FLL is out of lock right now
+    pub fll_unlocked: bool,
+    // Latched: the FLL has lost lock since the history was last cleared
+    pub fll_unlock_history: bool,
+    // Latched: summary of the oscillator fault flags, and the source of the fault NMI
+    pub osc_fault: bool,
+}
+
+impl ClockFaults {
+    pub fn any(&self) -> bool {
+        self.xt1 || self.dco || self.fll_unlocked || self.fll_unlock_history || self.osc_fault
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockSource {
+    Xt1,
+    Refoclk,
+    Vloclk,
+    Dcoclk,
+}
+
+// Sources the clocks are really running from. While XT1 is faulted the CS module feeds REFO to
+// everything that selects XT1, without changing the selection registers, and switches back on
+// its own once XT1 recovers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FailSafe {
+    pub mclk: ClockSource,
+    pub aclk: ClockSource,
+    pub fll_ref: ClockSource,
+}
+
+// Watches the CS fault flags. The fault NMI goes to the UNMI vector, which has to clear the
+// faults and re-enable the NMI, since the hardware turns OFIE off when the NMI is taken.
+pub struct FaultMonitor(());
+
+impl FaultMonitor {
+    pub fn new(_mclk: &Mclk) -> Self {
+        FaultMonitor(())
+    }
+
+    pub fn faults(&self) -> ClockFaults {
+        let csctl7 = unsafe { pac::CS::steal() }.csctl7.read();
+        let sfrifg1 = unsafe { pac::SFR::steal() }.sfrifg1.read();
+        ClockFaults {
+            xt1: csctl7.xt1offg().bit_is_set(),
+            dco: csctl7.dcoffg().bit_is_set(),
+            fll_unlocked: !csctl7.fllunlock().is_fllunlock_0(),
+            fll_unlock_history: !csctl7.fllunlockhis().is_fllunlockhis_0(),
+            osc_fault: sfrifg1.ofifg().bit_is_set(),
+        }
+    }
+
+    // Clears the latched flags and returns the faults that are still present afterwards
+    pub fn clear(&mut self) -> ClockFaults {
+        unsafe { pac::CS::steal() }.csctl7.modify(|_, w| {
+            w.xt1offg()
+                .clear_bit()
+                .dcoffg()
+                .clear_bit()
+                .fllunlockhis()
+                .fllunlockhis_0()
+        });
+        unsafe { pac::SFR::steal() }
+            .sfrifg1
+            .modify(|_, w| w.ofifg().clear_bit());
+        self.faults()
+    }
+
+    pub fn enable_nmi(&mut self) {
+        let sfr = unsafe { pac::SFR::steal() };
+        sfr.sfrifg1.modify(|_, w| w.ofifg().clear_bit());
+        sfr.sfrie1.modify(|_, w| w.ofie().set_bit());
+    }
+
+    pub fn disable_nmi(&mut self) {
+        unsafe { pac::SFR::steal() }
+            .sfrie1
+            .modify(|_, w| w.ofie().clear_bit());
+    }
+
+    pub fn fail_safe(&self) -> FailSafe {
+        let cs = unsafe { pac::CS::steal() };
+        let xt1_fault = cs.csctl7.read().xt1offg().bit_is_set();
+        let sub = |src| match src {
+            ClockSource::Xt1 if xt1_fault => ClockSource::Refoclk,
+            src => src,
+        };
+
+        let csctl4 = cs.csctl4.read();
+        let mclk = match csctl4.selms().bits() {
+            0 => ClockSource::Dcoclk,
+            1 => ClockSource::Refoclk,
+            2 => ClockSource::Xt1,
+            _ => ClockSource::Vloclk,
+        };
+        let aclk = match csctl4.sela().bits() {
+            0 => ClockSource::Xt1,
+            1 => ClockSource::Refoclk,
+            _ => ClockSource::Vloclk,
+        };
+        let fll_ref = match cs.csctl3.read().selref().bits() {
+            0 => ClockSource::Xt1,
+            _ => ClockSource::Refoclk,
+        };
+
+        FailSafe {
+            mclk: sub(mclk),
+            aclk: sub(aclk),
+            fll_ref: sub(fll_ref),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::{GpioExt, PmmExt};
+    use crate::gpio_trait::{GpioPeriph, RegRead};
+    use crate::regs::mock;
+    use crate::time::U32Ext;
+    use crate::timer::TimerExt;
+    use crate::watchdog::{WdtClkPeriods, WdtExt};
+
+    fn cs() -> &'static pac::cs::RegisterBlock {
+        unsafe { pac::CS::steal() }
+    }
+
+    #[test]
+    fn freeze_dcoclk() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut fram = periph.FRCTL.constrain();
+        let (mclk, smclk, aclk) = periph
+            .CS
+            .constrain()
+            .mclk_dcoclk(8.mhz())
+            .unwrap()
+            .smclk_divide_2()
+            .aclk_vloclk()
+            .freeze(&mut fram);
+
+        assert_eq!(mclk.freq(), Hertz(244 * REFOCLK as u32));
+        assert_eq!(smclk.freq(), Hertz(122 * REFOCLK as u32));
+        assert_eq!(aclk.freq(), Hertz(VLOCLK as u32));
+        assert!(cs().csctl3.read().selref().is_refoclk());
+        assert!(cs().csctl1.read().dcorsel().is_dcorsel_3());
+        assert_eq!(cs().csctl2.read().flln().bits(), 243);
+        assert!(cs().csctl4.read().selms().is_dcoclkdiv());
+        assert!(cs().csctl4.read().sela().is_vloclk());
+        let csctl5 = cs().csctl5.read();
+        assert_eq!(csctl5.divm().bits(), 0);
+        assert_eq!(csctl5.divs().bits(), 1);
+        assert!(csctl5.smclkoff().bit_is_clear());
+    }
+
+    #[test]
+    fn freeze_refoclk_smclk_off() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut fram = periph.FRCTL.constrain();
+        let (mclk, aclk) = periph
+            .CS
+            .constrain()
+            .mclk_refoclk(8192.hz())
+            .unwrap()
+            .smclk_off()
+            .freeze(&mut fram);
+
+        assert_eq!(mclk.freq(), Hertz(8192));
+        assert_eq!(aclk.freq(), Hertz(REFOCLK as u32));
+        // FLL is left alone when the DCO isn't used
+        assert_eq!(cs().csctl2.read().bits(), 0);
+        assert!(cs().csctl4.read().selms().is_refoclk());
+        let csctl5 = cs().csctl5.read();
+        assert_eq!(csctl5.divm().bits(), 2);
+        assert!(csctl5.smclkoff().bit_is_set());
+    }
+
+    #[test]
+    fn fram_wait_states() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut fram = periph.FRCTL.constrain();
+        let frctl0 = || unsafe { pac::FRCTL::steal() }.frctl0.read();
+        let freeze = |fram: &mut Fram, mhz: u32| {
+            let cs = unsafe { pac::Peripherals::steal() }.CS;
+            cs.constrain()
+                .mclk_dcoclk(mhz.mhz())
+                .unwrap()
+                .smclk_off()
+                .freeze(fram);
+        };
+
+        freeze(&mut fram, 8);
+        assert_eq!(frctl0().nwaits().bits(), 0);
+        freeze(&mut fram, 16);
+        assert_eq!(frctl0().nwaits().bits(), 1);
+        assert_eq!(frctl0().frctlpw().bits(), FRCTL_PASSWORD);
+        freeze(&mut fram, 24);
+        assert_eq!(frctl0().nwaits().bits(), 2);
+        freeze(&mut fram, 1);
+        assert_eq!(frctl0().nwaits().bits(), 0);
+    }
+
+    #[test]
+    fn xt1_fll_ref_and_aclk() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut fram = periph.FRCTL.constrain();
+        let pmm = periph.PMM.freeze();
+        let p2 = periph.P2.constrain().unlock(&pmm).split();
+        let xin = p2.p2_7.alternate2(&p2.psel);
+        let xout = p2.p2_6.alternate2(&p2.psel);
+        let xt1 = Xt1::crystal(xin, xout, Xt1Drive::XT1DRIVE_3);
+
+        let (mclk, _smclk, aclk) = periph
+            .CS
+            .constrain()
+            .fll_ref_xt1(&xt1)
+            .mclk_dcoclk(4.mhz())
+            .unwrap()
+            .smclk_divide_1()
+            .aclk_xt1(&xt1)
+            .freeze(&mut fram);
+
+        assert_eq!(mclk.freq(), Hertz(122 * XT1CLK as u32));
+        assert_eq!(aclk.freq(), Hertz(XT1CLK as u32));
+        let csctl6 = cs().csctl6.read();
+        assert!(csctl6.xts().is_xts_0());
+        assert!(csctl6.xt1bypass().bit_is_clear());
+        assert!(csctl6.xt1drive().is_xt1drive_3());
+        assert!(cs().csctl3.read().selref().is_xt1clk());
+        assert!(cs().csctl4.read().sela().is_xt1clk());
+        assert!(cs().csctl4.read().selms().is_dcoclkdiv());
+    }
+
+    #[test]
+    fn xt1_bypass_mclk() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut fram = periph.FRCTL.constrain();
+        let pmm = periph.PMM.freeze();
+        let p2 = periph.P2.constrain().unlock(&pmm).split();
+        let xt1 = Xt1::bypass(p2.p2_7.alternate2(&p2.psel), 20.khz()).unwrap();
+
+        let (mclk, aclk) = periph
+            .CS
+            .constrain()
+            .mclk_xt1(&xt1, 10.khz())
+            .unwrap()
+            .smclk_off()
+            .freeze(&mut fram);
+
+        assert_eq!(mclk.freq(), Hertz(10000));
+        assert_eq!(aclk.freq(), Hertz(REFOCLK as u32));
+        assert!(cs().csctl6.read().xt1bypass().bit_is_set());
+        assert!(cs().csctl4.read().selms().is_xt1clk());
+        assert_eq!(cs().csctl5.read().divm().bits(), 1);
+    }
+
+    #[test]
+    fn xt1_bypass_range() {
+        mock::reset();
+        let xt1 = |hz| {
+            let periph = unsafe { pac::Peripherals::steal() };
+            let pmm = periph.PMM.freeze();
+            let p2 = periph.P2.constrain().unlock(&pmm).split();
+            Xt1::bypass(p2.p2_7.alternate2(&p2.psel), Hertz(hz))
+        };
+        assert!(matches!(
+            xt1(XT1_BYPASS_MAX + 1),
+            Err(ClockFreqError::TooHigh)
+        ));
+        assert!(matches!(
+            xt1(XT1_BYPASS_MIN - 1),
+            Err(ClockFreqError::TooLow)
+        ));
+
+        // 24 MHz from 10 kHz needs a multiplier of 2400, which FLLN can't hold
+        let xt1 = xt1(XT1_BYPASS_MIN).unwrap();
+        let conf = ClocksConfig::new().fll_ref_xt1(&xt1);
+        assert!(matches!(
+            conf.mclk_dcoclk(Hertz(24_000_000)),
+            Err(ClockFreqError::TooHigh)
+        ));
+        let conf = ClocksConfig::new().fll_ref_xt1(&xt1);
+        assert_eq!(
+            conf.mclk_dcoclk(Hertz(10_240_000)).unwrap().mclk(),
+            Hertz(10_240_000)
+        );
+    }
+
+    #[test]
+    fn dco_autotrim() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut fram = periph.FRCTL.constrain();
+        let (mclk, _aclk) = periph
+            .CS
+            .constrain()
+            .mclk_dcoclk(16.mhz())
+            .unwrap()
+            .dco_autotrim()
+            .smclk_off()
+            .freeze(&mut fram);
+
+        // Registers hold whatever was last written, so the very first tap is already centered
+        assert_eq!(mclk.dco_trim(), DcoTrim { ftrim: 3, tap: 256 });
+        assert!(cs().csctl1.read().dcoftrimen().bit_is_set());
+        assert!(cs().csctl1.read().dcorsel().is_dcorsel_5());
+    }
+
+    // Runs the search against a DCO that the FLL settles at taps[DCOFTRIM], starting from
+    // DCOFTRIM = 3. Returns the trim it settled on and every DCOFTRIM it tried.
+    fn trim_search(taps: [u16; 8]) -> (DcoTrim, Vec<u8>) {
+        let cs = cs();
+        cs.csctl1
+            .write(|w| unsafe { w.dcoftrim().bits(DCO_FTRIM_START) });
+        let mut tried = Vec::new();
+        dco_trim_search(cs, || {
+            let ftrim = cs.csctl1.read().dcoftrim().bits();
+            tried.push(ftrim);
+            cs.csctl0
+                .write(|w| unsafe { w.dco().bits(taps[ftrim as usize]) });
+        });
+        let trim = DcoTrim {
+            ftrim: cs.csctl1.read().dcoftrim().bits(),
+            tap: cs.csctl0.read().dco().bits(),
+        };
+        (trim, tried)
+    }
+
+    #[test]
+    fn dco_trim_crossing() {
+        mock::reset();
+        // Tap too high at 3, close at 4, past the middle at 5. The closest one wins, not the last.
+        assert_eq!(
+            trim_search([0, 0, 0, 320, 262, 200, 0, 0]),
+            (DcoTrim { ftrim: 4, tap: 262 }, vec![3, 4, 5])
+        );
+        // Same from below
+        assert_eq!(
+            trim_search([0, 300, 251, 190, 0, 0, 0, 0]),
+            (DcoTrim { ftrim: 2, tap: 251 }, vec![3, 2, 1])
+        );
+    }
+
+    #[test]
+    fn dco_trim_no_crossing() {
+        mock::reset();
+        // The tap never reaches the middle, so the search stops at the end of the DCOFTRIM range
+        assert_eq!(
+            trim_search([230, 220, 210, 200, 0, 0, 0, 0]),
+            (DcoTrim { ftrim: 0, tap: 230 }, vec![3, 2, 1, 0])
+        );
+        assert_eq!(
+            trim_search([0, 0, 0, 400, 380, 360, 340, 320]),
+            (DcoTrim { ftrim: 7, tap: 320 }, vec![3, 4, 5, 6, 7])
+        );
+    }
+
+    #[test]
+    fn dco_cached_trim() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut fram = periph.FRCTL.constrain();
+        let trim = DcoTrim { ftrim: 5, tap: 100 };
+        let (mclk, _aclk) = periph
+            .CS
+            .constrain()
+            .mclk_dcoclk(16.mhz())
+            .unwrap()
+            .dco_trim(trim)
+            .smclk_off()
+            .freeze(&mut fram);
+
+        assert_eq!(mclk.dco_trim(), trim);
+        assert!(cs().csctl1.read().dcoftrimen().bit_is_set());
+    }
+
+    #[test]
+    fn xt1_fault_fail_safe() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut fram = periph.FRCTL.constrain();
+        let pmm = periph.PMM.freeze();
+        let p2 = periph.P2.constrain().unlock(&pmm).split();
+        let xt1 = Xt1::bypass(p2.p2_7.alternate2(&p2.psel), Hertz(XT1CLK as u32)).unwrap();
+        let (mclk, _smclk, _aclk) = periph
+            .CS
+            .constrain()
+            .fll_ref_xt1(&xt1)
+            .mclk_dcoclk(8.mhz())
+            .unwrap()
+            .smclk_divide_1()
+            .aclk_xt1(&xt1)
+            .freeze(&mut fram);
+
+        let mut monitor = FaultMonitor::new(&mclk);
+        monitor.enable_nmi();
+        assert!(unsafe { pac::SFR::steal() }
+            .sfrie1
+            .read()
+            .ofie()
+            .bit_is_set());
+        assert!(!monitor.faults().any());
+        assert_eq!(monitor.fail_safe().aclk, ClockSource::Xt1);
+
+        cs().csctl7.modify(|_, w| w.xt1offg().set_bit());
+        unsafe { pac::SFR::steal() }
+            .sfrifg1
+            .modify(|_, w| w.ofifg().set_bit());
+        let faults = monitor.faults();
+        assert!(faults.xt1 && faults.osc_fault && !faults.dco);
+        assert_eq!(
+            monitor.fail_safe(),
+            FailSafe {
+                mclk: ClockSource::Dcoclk,
+                aclk: ClockSource::Refoclk,
+                fll_ref: ClockSource::Refoclk,
+            }
+        );
+
+        assert!(!monitor.clear().any());
+        assert_eq!(monitor.fail_safe().fll_ref, ClockSource::Xt1);
+    }
+
+    #[test]
+    fn reconfigure_clocks() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut fram = periph.FRCTL.constrain();
+        let mut clocks = periph
+            .CS
+            .constrain()
+            .mclk_dcoclk(16.mhz())
+            .unwrap()
+            .smclk_divide_2()
+            .freeze_clocks(&mut fram);
+        assert_eq!(clocks.mclk().freq(), Hertz(488 * REFOCLK as u32));
+        assert_eq!(clocks.smclk().freq(), Hertz(244 * REFOCLK as u32));
+        assert_eq!(fram.wait_states(), 1);
+
+        // The timer and watchdog borrow the clocks, so they have to be gone before the clocks
+        // can be changed
+        let wdt = {
+            let parts = periph
+                .TB0
+                .constrain()
+                .use_smclk(clocks.smclk())
+                .to_periodic();
+            assert_eq!(parts.timer.ticks(1.ms()), Some(7995));
+            let wdt = periph.WDT_A.constrain().set_smclk(clocks.smclk());
+            assert!(matches!(wdt.periods(1.ms()), Some(WdtClkPeriods::_8192)));
+            wdt.set_vloclk()
+        };
+
+        clocks.reconfigure(&mut fram, |conf| {
+            conf.mclk_refoclk(Hertz(REFOCLK as u32))
+                .unwrap()
+                .aclk_vloclk()
+                .smclk_divide_2()
+        });
+        assert_eq!(clocks.mclk().freq(), Hertz(REFOCLK as u32));
+        assert_eq!(clocks.smclk().freq(), Hertz(REFOCLK as u32 / 2));
+        assert_eq!(clocks.aclk().freq(), Hertz(VLOCLK as u32));
+        assert_eq!(fram.wait_states(), 0);
+        assert!(cs().csctl4.read().selms().is_refoclk());
+        assert_eq!(cs().csctl5.read().divs().bits(), 1);
+
+        let wdt = wdt.set_smclk(clocks.smclk());
+        assert!(matches!(wdt.periods(1.ms()), Some(WdtClkPeriods::_64)));
+    }
+
+    #[test]
+    fn clock_outputs() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut fram = periph.FRCTL.constrain();
+        let pmm = periph.PMM.freeze();
+        let p1 = periph.P1.constrain().unlock(&pmm).split();
+        let p3 = periph.P3.constrain().unlock(&pmm).split();
+        let (mclk, smclk, aclk) = periph
+            .CS
+            .constrain()
+            .mclk_refoclk(Hertz(REFOCLK as u32))
+            .unwrap()
+            .smclk_divide_1()
+            .freeze(&mut fram);
+
+        let mclk_out = mclk.output(p3.p3_0.to_output(&p3.pdir).alternate1(&p3.psel));
+        let smclk_out = smclk.output(p3.p3_4.to_output(&p3.pdir).alternate1(&p3.psel));
+        let aclk_out = aclk.output(p1.p1_1.to_output(&p1.pdir).alternate2(&p1.psel));
+
+        let port3 = unsafe { pac::p3::RegisterBlock::steal() };
+        assert_eq!(port3.pxdir().rd(), 0x11);
+        assert_eq!(port3.pxsel0().rd(), 0x11);
+        assert_eq!(port3.pxsel1().rd(), 0x00);
+        let port1 = unsafe { pac::p1::RegisterBlock::steal() };
+        assert_eq!(port1.pxdir().rd() & 0x02, 0x02);
+        assert_eq!(port1.pxsel0().rd() & 0x02, 0x00);
+        assert_eq!(port1.pxsel1().rd() & 0x02, 0x02);
+
+        let _mclk_pin = mclk_out.release();
+        let _smclk_pin = smclk_out.release();
+        let _aclk_pin = aclk_out.release();
+    }
+
+    #[test]
+    fn csctl8_untouched() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut fram = periph.FRCTL.constrain();
+        cs().csctl8.write(|w| unsafe { w.bits(0x000F) });
+        let (mclk, _aclk) = periph
+            .CS
+            .constrain()
+            .mclk_refoclk(Hertz(REFOCLK as u32))
+            .unwrap()
+            .smclk_off()
+            .freeze(&mut fram);
+        assert_eq!(cs().csctl8.read().bits(), 0x000F);
+        assert!(mclk.modclk().is_some());
+    }
+
+    #[test]
+    fn smclk_freq_exact() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut fram = periph.FRCTL.constrain();
+        let (mclk, smclk, _aclk) = periph
+            .CS
+            .constrain()
+            .modclk_request()
+            .mclk_dcoclk(8.mhz())
+            .unwrap()
+            .smclk_freq(Hertz(61 * REFOCLK as u32))
+            .unwrap()
+            .freeze(&mut fram);
+        assert_eq!(smclk.freq(), Hertz(61 * REFOCLK as u32));
+        assert_eq!(cs().csctl5.read().divs().bits(), 2);
+        assert_eq!(mclk.modclk().map(|m| m.freq()), Some(Hertz(MODCLK)));
+
+        let smclk_freq = |hz| {
+            unsafe { pac::Peripherals::steal() }
+                .CS
+                .constrain()
+                .mclk_vloclk(Hertz(VLOCLK as u32 / 16))
+                .unwrap()
+                .smclk_freq(Hertz(hz))
+        };
+        assert!(matches!(
+            smclk_freq(VLOCLK as u32 / 32),
+            Err(ClockFreqError::Inexact)
+        ));
+        assert!(matches!(
+            smclk_freq(VLOCLK as u32),
+            Err(ClockFreqError::TooHigh)
+        ));
+        assert!(matches!(
+            smclk_freq(VLOCLK as u32 / 256),
+            Err(ClockFreqError::TooLow)
+        ));
+        assert!(smclk_freq(VLOCLK as u32 / 16).is_ok());
+
+        // A frequency that doesn't fit in Hertz is too high
+        assert!(matches!(
+            unsafe { pac::Peripherals::steal() }
+                .CS
+                .constrain()
+                .mclk_dcoclk(5_000.mhz()),
+            Err(ClockFreqError::TooHigh)
+        ));
+    }
+
+    const CONST_CLOCKS: ClocksConfig<SmclkDefined, ()> =
+        match ClocksConfig::new().mclk_dcoclk(Hertz(16_000_000)) {
+            Ok(conf) => conf.smclk_divide_4().aclk_vloclk(),
+            Err(_) => panic!("MCLK out of range"),
+        };
+    const CONST_SMCLK: Hertz = CONST_CLOCKS.smclk();
+
+    // Would fail to build if the range checks couldn't run in a const context
+    const _: () = assert!(matches!(
+        ClocksConfig::new().mclk_dcoclk(Hertz(DCOCLK_MAX + 1)),
+        Err(ClockFreqError::TooHigh)
+    ));
+    const _: () = assert!(matches!(
+        ClocksConfig::new().mclk_refoclk(Hertz(100)),
+        Err(ClockFreqError::TooLow)
+    ));
+
+    #[test]
+    fn const_config() {
+        mock::reset();
+        assert_eq!(CONST_CLOCKS.mclk(), Hertz(488 * REFOCLK as u32));
+        assert_eq!(CONST_SMCLK, Hertz(122 * REFOCLK as u32));
+        assert_eq!(CONST_CLOCKS.aclk(), Hertz(VLOCLK as u32));
+
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut fram = periph.FRCTL.constrain();
+        let (mclk, smclk, _aclk) = periph.CS.constrain_with(CONST_CLOCKS).freeze(&mut fram);
+        assert_eq!(mclk.freq(), CONST_CLOCKS.mclk());
+        assert_eq!(smclk.freq(), CONST_SMCLK);
+        assert_eq!(cs().csctl2.read().flln().bits(), 487);
+        assert_eq!(cs().csctl5.read().divs().bits(), 2);
+    }
 
-    fn freq(&self) -> u16 {
-        self.0
+    #[test]
+    fn calibration_maths() {
+        mock::reset();
+        // A 9.4 kHz VLO lasts 3404 ticks of a 1 MHz SMCLK over 32 periods
+        assert_eq!(measured_freq(1_000_000, 32, 3404), Some(9401));
+        assert_eq!(
+            measured_freq(8 * REFOCLK as u32, 32, 32 * 8),
+            Some(REFOCLK as u32)
+        );
+        // A reference slower than the clock being measured can't be trusted
+        assert_eq!(measured_freq(1_000_000, 32, 32), Some(1_000_000));
+        assert_eq!(measured_freq(1_000_000, 32, 31), None);
+        assert_eq!(measured_freq(1_000_000, 32, 0), None);
     }
 }
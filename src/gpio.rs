@@ -1,4 +1,12 @@
+use crate::gpio_trait::*;
+use crate::regs::Regs;
+use core::cell::Cell;
+use core::convert::Infallible;
 use core::marker::PhantomData;
+use embedded_hal::digital::v2::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
+#[cfg(not(test))]
+use msp430::interrupt;
+use msp430::interrupt::{CriticalSection, Mutex};
 use msp430fr2355 as pac;
 
 pub trait PmmExt {
@@ -9,7 +17,7 @@ pub struct Pmm(());
 
 impl PmmExt for pac::PMM {
     fn freeze(self) -> Pmm {
-        self.pm5ctl0.write(|w| w.locklpm5().locklpm5_0());
+        self.regs().pm5ctl0.write(|w| w.locklpm5().locklpm5_0());
         Pmm(())
     }
 }
@@ -22,9 +30,11 @@ pub trait GpioExt {
 
 pub struct Output<TOK>(PhantomData<TOK>);
 pub struct Input<PULL, INTR>(PhantomData<PULL>, PhantomData<INTR>);
-pub struct Alternate1;
-pub struct Alternate2;
-pub struct Alternate3;
+// Alternate functions keep the direction the pin had, since some modules use PxDIR to pick
+// between their input and output signals
+pub struct Alternate1<DIR>(PhantomData<DIR>);
+pub struct Alternate2<DIR>(PhantomData<DIR>);
+pub struct Alternate3<DIR>(PhantomData<DIR>);
 
 pub struct Pullup;
 pub struct Pulldown;
@@ -52,277 +62,376 @@ impl Known for Floating {}
 impl Known for Pulldown {}
 impl Known for Pullup {}
 
-pub struct P1<DIR, LOCK> {
-    periph: pac::P1,
-    _dir: PhantomData<DIR>,
-    _lock: PhantomData<LOCK>,
-}
-
 macro_rules! make_periph {
-    ($Px:ident, $periph:expr) => {
-        $Px {
-            periph: $periph,
-            _dir: PhantomData,
-            _lock: PhantomData,
-        }
-    };
-
     ($Px:ident) => {
         $Px {
+            _periph: PhantomData,
             _dir: PhantomData,
             _lock: PhantomData,
         }
     };
 }
 
-impl GpioExt for pac::P1 {
-    type Gpio = P1<Unknown, Locked>;
+// Whole port. Only exists until it's split, so it can write entire registers at once.
+pub struct Port<P, DIR, LOCK> {
+    _periph: PhantomData<P>,
+    _dir: PhantomData<DIR>,
+    _lock: PhantomData<LOCK>,
+}
 
-    fn constrain(self) -> Self::Gpio {
-        self.p1sel0.write(|w| unsafe { w.bits(0) });
-        self.p1sel1.write(|w| unsafe { w.bits(0) });
-        make_periph!(P1, self)
-    }
+pub type P1<DIR, LOCK> = Port<pac::p1::RegisterBlock, DIR, LOCK>;
+pub type P2<DIR, LOCK> = Port<pac::p2::RegisterBlock, DIR, LOCK>;
+pub type P3<DIR, LOCK> = Port<pac::p3::RegisterBlock, DIR, LOCK>;
+pub type P4<DIR, LOCK> = Port<pac::p4::RegisterBlock, DIR, LOCK>;
+pub type P5<DIR, LOCK> = Port<pac::p5::RegisterBlock, DIR, LOCK>;
+pub type P6<DIR, LOCK> = Port<pac::p6::RegisterBlock, DIR, LOCK>;
+
+macro_rules! gpio_ext {
+    ($Px:ident, $px:ident) => {
+        impl GpioExt for pac::$Px {
+            type Gpio = $Px<Unknown, Locked>;
+
+            fn constrain(self) -> Self::Gpio {
+                let periph = unsafe { pac::$px::RegisterBlock::steal() };
+                periph.pxsel0().wr(0);
+                periph.pxsel1().wr(0);
+                make_periph!(Port)
+            }
+        }
+    };
 }
 
-impl<PULL, LOCK> P1<Input<PULL, Disabled>, LOCK> {
-    pub fn pulldown(self) -> P1<Input<Pulldown, Disabled>, LOCK> {
-        self.periph.p1out.write(|w| unsafe { w.bits(0x00) });
-        self.periph.p1ren.write(|w| unsafe { w.bits(0xFF) });
-        make_periph!(P1, self.periph)
+gpio_ext!(P1, p1);
+gpio_ext!(P2, p2);
+gpio_ext!(P3, p3);
+gpio_ext!(P4, p4);
+gpio_ext!(P5, p5);
+gpio_ext!(P6, p6);
+
+impl<P: GpioPeriph, PULL, LOCK> Port<P, Input<PULL, Disabled>, LOCK> {
+    pub fn pulldown(self) -> Port<P, Input<Pulldown, Disabled>, LOCK> {
+        let periph = unsafe { P::steal() };
+        periph.pxout().wr(0x00);
+        periph.pxren().wr(0xFF);
+        make_periph!(Port)
     }
 
-    pub fn pullup(self) -> P1<Input<Pullup, Disabled>, LOCK> {
-        self.periph.p1out.write(|w| unsafe { w.bits(0xFF) });
-        self.periph.p1ren.write(|w| unsafe { w.bits(0xFF) });
-        make_periph!(P1, self.periph)
+    pub fn pullup(self) -> Port<P, Input<Pullup, Disabled>, LOCK> {
+        let periph = unsafe { P::steal() };
+        periph.pxout().wr(0xFF);
+        periph.pxren().wr(0xFF);
+        make_periph!(Port)
     }
 
-    pub fn float(self) -> P1<Input<Floating, Disabled>, LOCK> {
-        self.periph.p1ren.write(|w| unsafe { w.bits(0x00) });
-        make_periph!(P1, self.periph)
+    pub fn float(self) -> Port<P, Input<Floating, Disabled>, LOCK> {
+        unsafe { P::steal() }.pxren().wr(0x00);
+        make_periph!(Port)
     }
 }
 
-impl<PULL: Known> P1<Input<PULL, Disabled>, Unlocked> {
-    pub fn enable_intr_rising_edge(self) -> P1<Input<PULL, Enabled>, Unlocked> {
-        self.periph.p1ies.write(|w| unsafe { w.bits(0x00) });
-        self.periph.p1ifg.write(|w| unsafe { w.bits(0x00) });
-        self.periph.p1ie.write(|w| unsafe { w.bits(0xFF) });
-        make_periph!(P1, self.periph)
+impl<P: IntrPeriph, PULL: Known> Port<P, Input<PULL, Disabled>, Unlocked> {
+    pub fn enable_intr_rising_edge(self) -> Port<P, Input<PULL, Enabled>, Unlocked> {
+        let periph = unsafe { P::steal() };
+        periph.pxies().wr(0x00);
+        periph.pxifg().wr(0x00);
+        periph.pxie().wr(0xFF);
+        make_periph!(Port)
     }
 
-    pub fn enable_intr_falling_edge(self) -> P1<Input<PULL, Enabled>, Unlocked> {
-        self.periph.p1ies.write(|w| unsafe { w.bits(0xFF) });
-        self.periph.p1ifg.write(|w| unsafe { w.bits(0x00) });
-        self.periph.p1ie.write(|w| unsafe { w.bits(0xFF) });
-        make_periph!(P1, self.periph)
+    pub fn enable_intr_falling_edge(self) -> Port<P, Input<PULL, Enabled>, Unlocked> {
+        let periph = unsafe { P::steal() };
+        periph.pxies().wr(0xFF);
+        periph.pxifg().wr(0x00);
+        periph.pxie().wr(0xFF);
+        make_periph!(Port)
     }
 }
 
-impl<PULL> P1<Input<PULL, Enabled>, Unlocked> {
-    pub fn disable_intr(self) -> P1<Input<PULL, Disabled>, Unlocked> {
-        self.periph.p1ie.write(|w| unsafe { w.bits(0x00) });
-        make_periph!(P1, self.periph)
+impl<P: IntrPeriph, PULL> Port<P, Input<PULL, Enabled>, Unlocked> {
+    pub fn disable_intr(self) -> Port<P, Input<PULL, Disabled>, Unlocked> {
+        unsafe { P::steal() }.pxie().wr(0x00);
+        make_periph!(Port)
     }
 }
 
-impl<PULL: Known, INTR> P1<Input<PULL, INTR>, Unlocked> {
+impl<P: GpioPeriph, PULL: Known, INTR> Port<P, Input<PULL, INTR>, Unlocked> {
     pub fn read(&self) -> u8 {
-        self.periph.p1in.read().bits()
+        unsafe { P::steal() }.pxin().rd()
     }
+}
 
+impl<P: IntrPeriph, PULL: Known, INTR> Port<P, Input<PULL, INTR>, Unlocked> {
     pub fn clear_intr(&mut self) {
-        self.periph.p1ifg.write(|w| unsafe { w.bits(0x00) });
+        unsafe { P::steal() }.pxifg().wr(0x00);
     }
 
     pub fn set_intr(&mut self) {
-        self.periph.p1ifg.write(|w| unsafe { w.bits(0xFF) });
+        unsafe { P::steal() }.pxifg().wr(0xFF);
     }
 }
 
 // Don't need POUT token because we own the whole register already
-impl P1<Output<NoOutToken>, Unlocked> {
+impl<P: GpioPeriph> Port<P, Output<NoOutToken>, Unlocked> {
     pub fn write(&mut self, val: u8) {
-        self.periph.p1out.write(|w| unsafe { w.bits(val) });
+        unsafe { P::steal() }.pxout().wr(val);
     }
 
     pub fn toggle(&mut self) {
-        self.periph.p1out.modify(|r, w| unsafe { w.bits(r.bits()) });
+        unsafe { P::steal() }.pxout().toggle(0xFF);
+    }
+
+    // Masked writes only touch the bits set in the mask. set/clear/toggle are single instructions.
+    pub fn set_mask(&mut self, mask: u8) {
+        unsafe { P::steal() }.pxout().set(mask);
+    }
+
+    pub fn clear_mask(&mut self, mask: u8) {
+        unsafe { P::steal() }.pxout().clear(mask);
+    }
+
+    pub fn toggle_mask(&mut self, mask: u8) {
+        unsafe { P::steal() }.pxout().toggle(mask);
+    }
+
+    pub fn write_masked(&mut self, mask: u8, val: u8) {
+        masked(unsafe { P::steal() }.pxout(), mask, val);
     }
 }
 
-impl<DIR: ConvertToInput, LOCK> P1<DIR, LOCK> {
-    pub fn to_input(self) -> P1<Input<Unknown, Disabled>, LOCK> {
-        self.periph.p1dir.write(|w| unsafe { w.bits(0x00) });
-        make_periph!(P1, self.periph)
+impl<P: GpioPeriph, DIR: ConvertToInput, LOCK> Port<P, DIR, LOCK> {
+    pub fn to_input(self) -> Port<P, Input<Unknown, Disabled>, LOCK> {
+        unsafe { P::steal() }.pxdir().wr(0x00);
+        make_periph!(Port)
     }
 }
 
-impl<DIR: ConvertToOutput, LOCK> P1<DIR, LOCK> {
-    pub fn to_output(self) -> P1<Output<NoOutToken>, LOCK> {
-        self.periph.p1dir.write(|w| unsafe { w.bits(0xFF) });
-        make_periph!(P1, self.periph)
+impl<P: GpioPeriph, DIR: ConvertToOutput, LOCK> Port<P, DIR, LOCK> {
+    pub fn to_output(self) -> Port<P, Output<NoOutToken>, LOCK> {
+        unsafe { P::steal() }.pxdir().wr(0xFF);
+        make_periph!(Port)
     }
 }
 
-impl<DIR> P1<DIR, Locked> {
-    pub fn unlock(self, _lock: &Pmm) -> P1<DIR, Unlocked> {
-        make_periph!(P1, self.periph)
+impl<P, DIR> Port<P, DIR, Locked> {
+    pub fn unlock(self, _lock: &Pmm) -> Port<P, DIR, Unlocked> {
+        make_periph!(Port)
     }
 }
 
 pub struct PSEL(());
 pub struct POUT(());
+pub struct PDIR(());
+pub struct PREN(());
 
-impl<DIR, LOCK> P1<DIR, LOCK> {
-    pub fn split(self) -> Parts<DIR, DIR, LOCK> {
-        Parts {
-            psel: PSEL(()),
-            pout: POUT(()),
-            p1_0: make_periph!(P1_0),
-            p1_1: make_periph!(P1_1),
-        }
+// Single pin of a port, identified by a GpioPin such as Port1<Pin0>
+pub struct Pin<PIN, DIR, LOCK> {
+    _periph: PhantomData<PIN>,
+    _dir: PhantomData<DIR>,
+    _lock: PhantomData<LOCK>,
+}
+
+impl<PIN: GpioPin, PULL: Known, INTR> InputPin for Pin<PIN, Input<PULL, INTR>, Unlocked> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(pin_in::<PIN::Periph>(PIN::pin()))
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.is_high().map(|b| !b)
     }
 }
 
-pub struct Parts<DIR0, DIR1, LOCK> {
-    pub psel: PSEL,
-    pub pout: POUT,
-    pub p1_0: P1_0<DIR0, LOCK>,
-    pub p1_1: P1_1<DIR1, LOCK>,
+impl<PIN> Pin<PIN, Output<NoOutToken>, Unlocked> {
+    pub fn enable(self, _pout: &POUT) -> Pin<PIN, Output<OutToken<'_>>, Unlocked> {
+        make_periph!(Pin)
+    }
 }
 
-// P1.0
-pub struct P1_0<DIR, LOCK> {
-    _dir: PhantomData<DIR>,
-    _lock: PhantomData<LOCK>,
+// Only outputs that hold a POUT token can touch their PxOUT bit
+impl<'out, PIN: GpioPin> OutputPin for Pin<PIN, Output<OutToken<'out>>, Unlocked> {
+    type Error = Infallible;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        pin_set::<PIN::Periph>(PIN::pin());
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        pin_clear::<PIN::Periph>(PIN::pin());
+        Ok(())
+    }
 }
 
-impl<PULL: Known, INTR> P1_0<Input<PULL, INTR>, Unlocked> {
-    pub fn read(&self) -> bool {
-        unsafe { &*pac::P1::ptr() }.p1in.read().bits() & (1 << 0) != 0
+impl<'out, PIN: GpioPin> StatefulOutputPin for Pin<PIN, Output<OutToken<'out>>, Unlocked> {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(pin_out::<PIN::Periph>(PIN::pin()))
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        self.is_set_high().map(|b| !b)
     }
 }
 
-impl P1_0<Output<NoOutToken>, Unlocked> {
-    pub fn enable<'out>(self, _pout: &'out POUT) -> P1_0<Output<OutToken<'out>>, Unlocked> {
-        make_periph!(P1_0)
+impl<'out, PIN: GpioPin> ToggleableOutputPin for Pin<PIN, Output<OutToken<'out>>, Unlocked> {
+    type Error = Infallible;
+
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        pin_toggle::<PIN::Periph>(PIN::pin());
+        Ok(())
     }
 }
 
-impl<'out> P1_0<Output<OutToken<'out>>, Unlocked> {
-    pub fn set_bit(&mut self) {
-        unsafe { &*pac::P1::ptr() }
-            .p1out
-            .modify(|r, w| unsafe { w.bits(r.bits() | 1) });
+impl<PIN: GpioPin, PULL, LOCK> Pin<PIN, Input<PULL, Disabled>, LOCK> {
+    pub fn pulldown(self, _pren: &PREN) -> Pin<PIN, Input<Pulldown, Disabled>, LOCK> {
+        let periph = unsafe { PIN::Periph::steal() };
+        periph.pxout().clear(PIN::mask());
+        periph.pxren().set(PIN::mask());
+        make_periph!(Pin)
     }
 
-    pub fn clear_bit(&mut self) {
-        unsafe { &*pac::P1::ptr() }
-            .p1out
-            .modify(|r, w| unsafe { w.bits(r.bits() & !1) });
+    pub fn pullup(self, _pren: &PREN) -> Pin<PIN, Input<Pullup, Disabled>, LOCK> {
+        let periph = unsafe { PIN::Periph::steal() };
+        periph.pxout().set(PIN::mask());
+        periph.pxren().set(PIN::mask());
+        make_periph!(Pin)
     }
-}
 
-impl<DIR> P1_0<DIR, Locked> {
-    pub fn unlock(self, _lock: &Pmm) -> P1_0<DIR, Unlocked> {
-        make_periph!(P1_0)
+    pub fn float(self, _pren: &PREN) -> Pin<PIN, Input<Floating, Disabled>, LOCK> {
+        unsafe { PIN::Periph::steal() }.pxren().clear(PIN::mask());
+        make_periph!(Pin)
     }
 }
 
-impl<DIR, LOCK> P1_0<DIR, LOCK> {
-    pub fn alternate1(self, _psel: &PSEL) -> P1_0<Alternate1, LOCK> {
-        let periph = unsafe { &*pac::P1::ptr() };
-        periph.p1sel0.modify(|r, w| unsafe { w.bits(r.bits() | 1) });
-        periph
-            .p1sel1
-            .modify(|r, w| unsafe { w.bits(r.bits() & !1) });
-        make_periph!(P1_0)
+impl<PIN: GpioPin, DIR: ConvertToInput, LOCK> Pin<PIN, DIR, LOCK> {
+    pub fn to_input(self, _pdir: &PDIR) -> Pin<PIN, Input<Unknown, Disabled>, LOCK> {
+        unsafe { PIN::Periph::steal() }.pxdir().clear(PIN::mask());
+        make_periph!(Pin)
     }
+}
 
-    pub fn alternate2(self, _psel: &PSEL) -> P1_0<Alternate2, LOCK> {
-        let periph = unsafe { &*pac::P1::ptr() };
-        periph
-            .p1sel0
-            .modify(|r, w| unsafe { w.bits(r.bits() & !1) });
-        periph.p1sel1.modify(|r, w| unsafe { w.bits(r.bits() | 1) });
-        make_periph!(P1_0)
+impl<PIN: GpioPin, DIR: ConvertToOutput, LOCK> Pin<PIN, DIR, LOCK> {
+    pub fn to_output(self, _pdir: &PDIR) -> Pin<PIN, Output<NoOutToken>, LOCK> {
+        unsafe { PIN::Periph::steal() }.pxdir().set(PIN::mask());
+        make_periph!(Pin)
     }
+}
 
-    pub fn alternate3(self, _psel: &PSEL) -> P1_0<Alternate3, LOCK> {
-        let periph = unsafe { &*pac::P1::ptr() };
-        periph.p1sel0.modify(|r, w| unsafe { w.bits(r.bits() | 1) });
-        periph.p1sel1.modify(|r, w| unsafe { w.bits(r.bits() | 1) });
-        make_periph!(P1_0)
+impl<PIN, DIR> Pin<PIN, DIR, Locked> {
+    pub fn unlock(self, _lock: &Pmm) -> Pin<PIN, DIR, Unlocked> {
+        make_periph!(Pin)
     }
 }
 
-// P1.1
-pub struct P1_1<DIR, LOCK> {
-    _dir: PhantomData<DIR>,
-    _lock: PhantomData<LOCK>,
+impl<PIN: GpioPin, DIR, LOCK> GpioPin for Pin<PIN, DIR, LOCK> {
+    type Periph = PIN::Periph;
+
+    fn pin() -> u8 {
+        PIN::pin()
+    }
 }
 
-impl<PULL: Known, INTR> P1_1<Input<PULL, INTR>, Unlocked> {
-    pub fn read(&self) -> bool {
-        unsafe { &*pac::P1::ptr() }.p1in.read().bits() & (1 << 0) != 0
+impl<PIN: GpioPin, DIR> Pin<PIN, DIR, Unlocked> {
+    pub fn downgrade(self) -> PxN<PIN::Periph, DIR> {
+        PxN {
+            pin: PIN::pin(),
+            _periph: PhantomData,
+            _dir: PhantomData,
+        }
     }
 }
 
-impl P1_1<Output<NoOutToken>, Unlocked> {
-    pub fn enable<'out>(self, _pout: &'out POUT) -> P1_1<Output<OutToken<'out>>, Unlocked> {
-        make_periph!(P1_1)
+impl<PIN: GpioPin, DIR, LOCK> Pin<PIN, DIR, LOCK> {
+    pub fn alternate1(self, _psel: &PSEL) -> Pin<PIN, Alternate1<DIR>, LOCK> {
+        let periph = unsafe { PIN::Periph::steal() };
+        periph.pxsel0().set(PIN::mask());
+        periph.pxsel1().clear(PIN::mask());
+        make_periph!(Pin)
+    }
+
+    pub fn alternate2(self, _psel: &PSEL) -> Pin<PIN, Alternate2<DIR>, LOCK> {
+        let periph = unsafe { PIN::Periph::steal() };
+        periph.pxsel0().clear(PIN::mask());
+        periph.pxsel1().set(PIN::mask());
+        make_periph!(Pin)
+    }
+
+    pub fn alternate3(self, _psel: &PSEL) -> Pin<PIN, Alternate3<DIR>, LOCK> {
+        let periph = unsafe { PIN::Periph::steal() };
+        periph.pxsel0().set(PIN::mask());
+        periph.pxsel1().set(PIN::mask());
+        make_periph!(Pin)
     }
 }
 
-impl<'out> P1_1<Output<OutToken<'out>>, Unlocked> {
-    pub fn set_bit(&mut self) {
-        unsafe { &*pac::P1::ptr() }
-            .p1out
-            .modify(|r, w| unsafe { w.bits(r.bits() | 1) });
+// Only ports 1-4 have interrupt capabilities
+impl<PIN: GpioPin, PULL: Known> Pin<PIN, Input<PULL, Disabled>, Unlocked>
+where
+    PIN::Periph: IntrPeriph,
+{
+    pub fn enable_intr_rising_edge(self) -> Pin<PIN, Input<PULL, Enabled>, Unlocked> {
+        pin_intr_edge::<PIN>(false);
+        make_periph!(Pin)
     }
 
-    pub fn clear_bit(&mut self) {
-        unsafe { &*pac::P1::ptr() }
-            .p1out
-            .modify(|r, w| unsafe { w.bits(r.bits() & !1) });
+    pub fn enable_intr_falling_edge(self) -> Pin<PIN, Input<PULL, Enabled>, Unlocked> {
+        pin_intr_edge::<PIN>(true);
+        make_periph!(Pin)
     }
 }
 
-impl<DIR> P1_1<DIR, Locked> {
-    pub fn unlock(self, _lock: &Pmm) -> P1_1<DIR, Unlocked> {
-        make_periph!(P1_1)
+impl<PIN: GpioPin, PULL> Pin<PIN, Input<PULL, Enabled>, Unlocked>
+where
+    PIN::Periph: IntrPeriph,
+{
+    pub fn disable_intr(self) -> Pin<PIN, Input<PULL, Disabled>, Unlocked> {
+        unsafe { PIN::Periph::steal() }.pxie().clear(PIN::mask());
+        make_periph!(Pin)
+    }
+
+    pub fn set_rising_edge(&mut self) {
+        pin_intr_edge::<PIN>(false);
+    }
+
+    pub fn set_falling_edge(&mut self) {
+        pin_intr_edge::<PIN>(true);
     }
 }
 
-impl<DIR, LOCK> P1_1<DIR, LOCK> {
-    pub fn alternate1(self, _psel: &PSEL) -> P1_1<Alternate1, LOCK> {
-        let periph = unsafe { &*pac::P1::ptr() };
-        periph.p1sel0.modify(|r, w| unsafe { w.bits(r.bits() | 1) });
-        periph
-            .p1sel1
-            .modify(|r, w| unsafe { w.bits(r.bits() & !1) });
-        make_periph!(P1_1)
+impl<PIN: GpioPin, PULL: Known, INTR> Pin<PIN, Input<PULL, INTR>, Unlocked>
+where
+    PIN::Periph: IntrPeriph,
+{
+    pub fn clear_intr(&mut self) {
+        unsafe { PIN::Periph::steal() }.pxifg().clear(PIN::mask());
     }
 
-    pub fn alternate2(self, _psel: &PSEL) -> P1_1<Alternate2, LOCK> {
-        let periph = unsafe { &*pac::P1::ptr() };
-        periph
-            .p1sel0
-            .modify(|r, w| unsafe { w.bits(r.bits() & !1) });
-        periph.p1sel1.modify(|r, w| unsafe { w.bits(r.bits() | 1) });
-        make_periph!(P1_1)
+    pub fn set_intr(&mut self) {
+        unsafe { PIN::Periph::steal() }.pxifg().set(PIN::mask());
     }
 
-    pub fn alternate3(self, _psel: &PSEL) -> P1_1<Alternate3, LOCK> {
-        let periph = unsafe { &*pac::P1::ptr() };
-        periph.p1sel0.modify(|r, w| unsafe { w.bits(r.bits() | 1) });
-        periph.p1sel1.modify(|r, w| unsafe { w.bits(r.bits() | 1) });
-        make_periph!(P1_1)
+    pub fn intr_pending(&self) -> bool {
+        unsafe { PIN::Periph::steal() }.pxifg().rd() & PIN::mask() != 0
     }
 }
 
+// Writing PxIES can set the pin's flag, so the interrupt is held off until the flag is cleared
+fn pin_intr_edge<PIN: GpioPin>(falling: bool)
+where
+    PIN::Periph: IntrPeriph,
+{
+    let periph = unsafe { PIN::Periph::steal() };
+    let mask = PIN::mask();
+    periph.pxie().clear(mask);
+    if falling {
+        periph.pxies().set(mask);
+    } else {
+        periph.pxies().clear(mask);
+    }
+    periph.pxifg().clear(mask);
+    periph.pxie().set(mask);
+}
+
 /**************************************************************************/
 // Proxy stuff starts here
 
@@ -338,234 +447,918 @@ trait WritePren {
     fn pren_on(&self) -> bool;
 }
 
+trait WritePsel {
+    fn psel0_on(&self) -> bool;
+    fn psel1_on(&self) -> bool;
+}
+
+trait WritePie {
+    fn pie_on(&self) -> bool;
+}
+
 macro_rules! make_proxy {
-    ($Px:ident) => {
-        $Px {
+    () => {
+        Proxy {
+            _periph: PhantomData,
             _dir: PhantomData,
             _lock: PhantomData,
+            io_changed: false,
+            sel_changed: false,
             out: false,
+            falling: false,
         }
     };
 
-    ($Px:ident, $out:expr) => {
-        $Px {
+    // Direction, level, resistor or interrupt change. The select bits stay as they were.
+    ($self:ident, $out:expr, $falling:expr) => {
+        Proxy {
+            _periph: PhantomData,
             _dir: PhantomData,
             _lock: PhantomData,
+            io_changed: true,
+            sel_changed: $self.sel_changed,
             out: $out,
+            falling: $falling,
+        }
+    };
+
+    // Function select change, which leaves everything else alone
+    (@sel $self:ident) => {
+        Proxy {
+            _periph: PhantomData,
+            _dir: PhantomData,
+            _lock: PhantomData,
+            io_changed: $self.io_changed,
+            sel_changed: true,
+            out: $self.out,
+            falling: $self.falling,
         }
     };
 }
 
-pub struct P1_0Proxy<DIR, LOCK> {
+// Stand-in for a pin during a batch write. Only the registers a proxy actually reconfigured get
+// written, so the other pins of the port, and a re-muxed pin's level, keep whatever state they
+// were in.
+pub struct Proxy<PIN, DIR, LOCK> {
+    _periph: PhantomData<PIN>,
     _dir: PhantomData<DIR>,
     _lock: PhantomData<LOCK>,
+    io_changed: bool,
+    sel_changed: bool,
     out: bool,
+    falling: bool,
 }
 
-impl<DIR, LOCK> WritePdir for P1_0Proxy<DIR, LOCK> {
+impl<PIN, DIR, LOCK> WritePdir for Proxy<PIN, DIR, LOCK> {
     default fn pdir_on(&self) -> bool {
         false
     }
 }
-impl<DIR, LOCK> WritePout for P1_0Proxy<DIR, LOCK> {
+impl<PIN, DIR, LOCK> WritePout for Proxy<PIN, DIR, LOCK> {
     default fn pout_on(&self) -> bool {
         false
     }
 }
-impl<DIR, LOCK> WritePren for P1_0Proxy<DIR, LOCK> {
+impl<PIN, DIR, LOCK> WritePren for Proxy<PIN, DIR, LOCK> {
     default fn pren_on(&self) -> bool {
         false
     }
 }
+impl<PIN, DIR, LOCK> WritePsel for Proxy<PIN, DIR, LOCK> {
+    default fn psel0_on(&self) -> bool {
+        false
+    }
+    default fn psel1_on(&self) -> bool {
+        false
+    }
+}
+impl<PIN, DIR, LOCK> WritePie for Proxy<PIN, DIR, LOCK> {
+    default fn pie_on(&self) -> bool {
+        false
+    }
+}
 
-impl<LOCK> WritePout for P1_0Proxy<Output<NoOutToken>, LOCK> {
+impl<PIN, LOCK> WritePout for Proxy<PIN, Output<NoOutToken>, LOCK> {
     fn pout_on(&self) -> bool {
         self.out
     }
 }
-impl<INTR, LOCK> WritePout for P1_0Proxy<Input<Pullup, INTR>, LOCK> {
+impl<PIN, INTR, LOCK> WritePout for Proxy<PIN, Input<Pullup, INTR>, LOCK> {
     fn pout_on(&self) -> bool {
         true
     }
 }
 
-impl<LOCK> WritePdir for P1_0Proxy<Output<NoOutToken>, LOCK> {
+impl<PIN, LOCK> WritePdir for Proxy<PIN, Output<NoOutToken>, LOCK> {
     fn pdir_on(&self) -> bool {
         true
     }
 }
 
-impl<INTR, LOCK> WritePren for P1_0Proxy<Input<Pullup, INTR>, LOCK> {
+impl<PIN, INTR, LOCK> WritePren for Proxy<PIN, Input<Pullup, INTR>, LOCK> {
     fn pren_on(&self) -> bool {
         true
     }
 }
-impl<INTR, LOCK> WritePren for P1_0Proxy<Input<Pulldown, INTR>, LOCK> {
+impl<PIN, INTR, LOCK> WritePren for Proxy<PIN, Input<Pulldown, INTR>, LOCK> {
     fn pren_on(&self) -> bool {
         true
     }
 }
 
-impl<PULL, LOCK> P1_0Proxy<Input<PULL, Disabled>, LOCK> {
-    pub fn pulldown(self) -> P1_0Proxy<Input<Pulldown, Disabled>, LOCK> {
-        make_proxy!(P1_0Proxy)
+impl<PIN, DIR, LOCK> WritePsel for Proxy<PIN, Alternate1<DIR>, LOCK> {
+    fn psel0_on(&self) -> bool {
+        true
     }
-
-    pub fn pullup(self) -> P1_0Proxy<Input<Pullup, Disabled>, LOCK> {
-        make_proxy!(P1_0Proxy)
+    fn psel1_on(&self) -> bool {
+        false
     }
-
-    pub fn float(self) -> P1_0Proxy<Input<Floating, Disabled>, LOCK> {
-        make_proxy!(P1_0Proxy)
+}
+impl<PIN, DIR, LOCK> WritePsel for Proxy<PIN, Alternate2<DIR>, LOCK> {
+    fn psel0_on(&self) -> bool {
+        false
+    }
+    fn psel1_on(&self) -> bool {
+        true
+    }
+}
+impl<PIN, DIR, LOCK> WritePsel for Proxy<PIN, Alternate3<DIR>, LOCK> {
+    fn psel0_on(&self) -> bool {
+        true
     }
+    fn psel1_on(&self) -> bool {
+        true
+    }
+}
+
+// Alternate pins write PxDIR, PxOUT and PxREN the same way the pin did before it was muxed
+macro_rules! alternate_proxy {
+    ($($Alt:ident),+) => {
+        $(
+            impl<PIN, DIR, LOCK> Proxy<PIN, $Alt<DIR>, LOCK> {
+                fn inner(&self) -> Proxy<PIN, DIR, LOCK> {
+                    make_proxy!(@sel self)
+                }
+            }
+
+            impl<PIN, DIR, LOCK> WritePdir for Proxy<PIN, $Alt<DIR>, LOCK> {
+                fn pdir_on(&self) -> bool {
+                    self.inner().pdir_on()
+                }
+            }
+            impl<PIN, DIR, LOCK> WritePout for Proxy<PIN, $Alt<DIR>, LOCK> {
+                fn pout_on(&self) -> bool {
+                    self.inner().pout_on()
+                }
+            }
+            impl<PIN, DIR, LOCK> WritePren for Proxy<PIN, $Alt<DIR>, LOCK> {
+                fn pren_on(&self) -> bool {
+                    self.inner().pren_on()
+                }
+            }
+        )+
+    };
 }
 
-impl<DIR: ConvertToInput, LOCK> P1_0Proxy<DIR, LOCK> {
-    pub fn to_input(self) -> P1_0Proxy<Input<Unknown, Disabled>, LOCK> {
-        make_proxy!(P1_0Proxy)
+alternate_proxy!(Alternate1, Alternate2, Alternate3);
+
+impl<PIN, PULL, LOCK> WritePie for Proxy<PIN, Input<PULL, Enabled>, LOCK> {
+    fn pie_on(&self) -> bool {
+        true
     }
 }
 
-impl<DIR: ConvertToOutput, LOCK> P1_0Proxy<DIR, LOCK> {
-    pub fn to_output(self) -> P1_0Proxy<Output<NoOutToken>, LOCK> {
-        make_proxy!(P1_0Proxy, false)
+impl<PIN, PULL, LOCK> Proxy<PIN, Input<PULL, Disabled>, LOCK> {
+    pub fn pulldown(self) -> Proxy<PIN, Input<Pulldown, Disabled>, LOCK> {
+        make_proxy!(self, false, false)
+    }
+
+    pub fn pullup(self) -> Proxy<PIN, Input<Pullup, Disabled>, LOCK> {
+        make_proxy!(self, false, false)
+    }
+
+    pub fn float(self) -> Proxy<PIN, Input<Floating, Disabled>, LOCK> {
+        make_proxy!(self, false, false)
     }
 }
 
-impl P1_0Proxy<Output<NoOutToken>, Unlocked> {
-    pub fn on(self) -> P1_0Proxy<Output<NoOutToken>, Unlocked> {
-        make_proxy!(P1_0Proxy, true)
+impl<PIN: GpioPin, PULL: Known> Proxy<PIN, Input<PULL, Disabled>, Unlocked>
+where
+    PIN::Periph: IntrPeriph,
+{
+    pub fn enable_intr_rising_edge(self) -> Proxy<PIN, Input<PULL, Enabled>, Unlocked> {
+        make_proxy!(self, false, false)
     }
 
-    pub fn off(self) -> P1_0Proxy<Output<NoOutToken>, Unlocked> {
-        make_proxy!(P1_0Proxy, false)
+    pub fn enable_intr_falling_edge(self) -> Proxy<PIN, Input<PULL, Enabled>, Unlocked> {
+        make_proxy!(self, false, true)
     }
 }
 
-pub struct P1_1Proxy<DIR, LOCK> {
-    _dir: PhantomData<DIR>,
-    _lock: PhantomData<LOCK>,
-    out: bool,
+impl<PIN: GpioPin, PULL> Proxy<PIN, Input<PULL, Enabled>, Unlocked>
+where
+    PIN::Periph: IntrPeriph,
+{
+    pub fn disable_intr(self) -> Proxy<PIN, Input<PULL, Disabled>, Unlocked> {
+        make_proxy!(self, false, false)
+    }
 }
 
-impl<DIR, LOCK> WritePdir for P1_1Proxy<DIR, LOCK> {
-    default fn pdir_on(&self) -> bool {
-        false
+impl<PIN, DIR: ConvertToInput, LOCK> Proxy<PIN, DIR, LOCK> {
+    pub fn to_input(self) -> Proxy<PIN, Input<Unknown, Disabled>, LOCK> {
+        make_proxy!(self, false, false)
     }
 }
-impl<DIR, LOCK> WritePout for P1_1Proxy<DIR, LOCK> {
-    default fn pout_on(&self) -> bool {
-        false
+
+impl<PIN, DIR: ConvertToOutput, LOCK> Proxy<PIN, DIR, LOCK> {
+    pub fn to_output(self) -> Proxy<PIN, Output<NoOutToken>, LOCK> {
+        make_proxy!(self, false, false)
     }
 }
-impl<DIR, LOCK> WritePren for P1_1Proxy<DIR, LOCK> {
-    default fn pren_on(&self) -> bool {
-        false
+
+impl<PIN> Proxy<PIN, Output<NoOutToken>, Unlocked> {
+    pub fn on(self) -> Proxy<PIN, Output<NoOutToken>, Unlocked> {
+        make_proxy!(self, true, false)
+    }
+
+    pub fn off(self) -> Proxy<PIN, Output<NoOutToken>, Unlocked> {
+        make_proxy!(self, false, false)
     }
 }
 
-impl<LOCK> WritePout for P1_1Proxy<Output<NoOutToken>, LOCK> {
-    fn pout_on(&self) -> bool {
-        self.out
+impl<PIN, DIR, LOCK> Proxy<PIN, DIR, LOCK> {
+    pub fn alternate1(self) -> Proxy<PIN, Alternate1<DIR>, LOCK> {
+        make_proxy!(@sel self)
+    }
+
+    pub fn alternate2(self) -> Proxy<PIN, Alternate2<DIR>, LOCK> {
+        make_proxy!(@sel self)
+    }
+
+    pub fn alternate3(self) -> Proxy<PIN, Alternate3<DIR>, LOCK> {
+        make_proxy!(@sel self)
     }
 }
-impl<INTR, LOCK> WritePout for P1_1Proxy<Input<Pullup, INTR>, LOCK> {
-    fn pout_on(&self) -> bool {
-        true
+
+// Register values collected from the proxies of one port. `mask` marks the pins whose direction,
+// level, resistor or interrupt was reconfigured and `sel_mask` the pins whose function changed.
+// Only those bits get written.
+#[derive(Default)]
+struct Batch {
+    mask: u8,
+    sel_mask: u8,
+    pdir: u8,
+    pout: u8,
+    pren: u8,
+    psel0: u8,
+    psel1: u8,
+    pie: u8,
+    pies: u8,
+}
+
+impl<PIN: GpioPin, DIR, LOCK> Proxy<PIN, DIR, LOCK> {
+    fn add_to(&self, batch: &mut Batch) {
+        let bit = PIN::mask();
+        let on = |b: bool| if b { bit } else { 0 };
+        if self.io_changed {
+            batch.mask |= bit;
+            batch.pdir |= on(self.pdir_on());
+            batch.pout |= on(self.pout_on());
+            batch.pren |= on(self.pren_on());
+            batch.pie |= on(self.pie_on());
+            batch.pies |= on(self.falling);
+        }
+        if self.sel_changed {
+            batch.sel_mask |= bit;
+            batch.psel0 |= on(self.psel0_on());
+            batch.psel1 |= on(self.psel1_on());
+        }
     }
 }
 
-impl<LOCK> WritePdir for P1_1Proxy<Output<NoOutToken>, LOCK> {
-    fn pdir_on(&self) -> bool {
-        true
+fn masked<R: RegRw8>(reg: &R, mask: u8, val: u8) {
+    reg.wr((reg.rd() & !mask) | (val & mask));
+}
+
+impl Batch {
+    fn write_intr_disable<P: IntrPeriph>(&self) {
+        unsafe { P::steal() }.pxie().clear(self.mask);
+    }
+
+    fn write<P: GpioPeriph>(&self) {
+        let periph = unsafe { P::steal() };
+        // Set the output latch and resistors before the direction so outputs come up at the
+        // requested level
+        masked(periph.pxout(), self.mask, self.pout);
+        masked(periph.pxren(), self.mask, self.pren);
+        masked(periph.pxdir(), self.mask, self.pdir);
+
+        // Pins where both select bits change get flipped in one write through PxSELC, so they
+        // never pass through another function on the way
+        let sel0 = periph.pxsel0().rd();
+        let sel1 = periph.pxsel1().rd();
+        let flip0 = (sel0 ^ self.psel0) & self.sel_mask;
+        let flip1 = (sel1 ^ self.psel1) & self.sel_mask;
+        let both = flip0 & flip1;
+        if both != 0 {
+            periph.pxselc().wr(both);
+        }
+        if flip0 & !both != 0 {
+            periph.pxsel0().toggle(flip0 & !both);
+        }
+        if flip1 & !both != 0 {
+            periph.pxsel1().toggle(flip1 & !both);
+        }
+    }
+
+    // Changing PxIES can set a flag, so flags are cleared before interrupts are turned back on
+    fn write_intr<P: IntrPeriph>(&self) {
+        let periph = unsafe { P::steal() };
+        masked(periph.pxies(), self.mask, self.pies);
+        periph.pxifg().clear(self.mask & self.pie);
+        periph.pxie().set(self.mask & self.pie);
     }
 }
 
-impl<INTR, LOCK> WritePren for P1_1Proxy<Input<Pullup, INTR>, LOCK> {
-    fn pren_on(&self) -> bool {
-        true
+macro_rules! gpio_parts {
+    ($Px:ident, $Parts:ident, $BatchParts:ident, $PortX:ident, $intr:ident =>
+     [$($pin:ident: $Pin:ident, $Proxy:ident, $Num:ident, $DIR:ident;)+]
+    ) => {
+        $(
+            pub type $Pin<DIR, LOCK> = Pin<$PortX<$Num>, DIR, LOCK>;
+            pub type $Proxy<DIR, LOCK> = Proxy<$PortX<$Num>, DIR, LOCK>;
+        )+
+
+        impl<DIR, LOCK> $Px<DIR, LOCK> {
+            pub fn split(self) -> $Parts<$(gpio_parts!(@dir $DIR, DIR),)+ LOCK> {
+                $Parts {
+                    psel: PSEL(()),
+                    pout: POUT(()),
+                    pdir: PDIR(()),
+                    pren: PREN(()),
+                    $($pin: make_periph!(Pin),)+
+                }
+            }
+        }
+
+        pub struct $Parts<$($DIR,)+ LOCK> {
+            pub psel: PSEL,
+            pub pout: POUT,
+            pub pdir: PDIR,
+            pub pren: PREN,
+            $(pub $pin: $Pin<$DIR, LOCK>,)+
+        }
+
+        pub struct $BatchParts<$($DIR,)+ LOCK> {
+            $(pub $pin: $Proxy<$DIR, LOCK>,)+
+        }
+
+        impl<$($DIR,)+ LOCK> $Parts<$($DIR,)+ LOCK> {
+            pub fn batch(self) -> $BatchParts<$($DIR,)+ LOCK> {
+                $BatchParts {
+                    $($pin: make_proxy!(),)+
+                }
+            }
+        }
+
+        impl<$($DIR,)+ LOCK> $BatchParts<$($DIR,)+ LOCK> {
+            pub fn write(self) -> $Parts<$($DIR,)+ LOCK> {
+                let mut batch = Batch::default();
+                $(self.$pin.add_to(&mut batch);)+
+
+                gpio_parts!(@$intr write_intr_disable, batch, $PortX);
+                batch.write::<<$PortX<Pin0> as GpioPin>::Periph>();
+                gpio_parts!(@$intr write_intr, batch, $PortX);
+
+                $Parts {
+                    psel: PSEL(()),
+                    pout: POUT(()),
+                    pdir: PDIR(()),
+                    pren: PREN(()),
+                    $($pin: make_periph!(Pin),)+
+                }
+            }
+        }
+    };
+
+    (@dir $DIR:ident, $Port:ident) => {
+        $Port
+    };
+
+    (@intr $write:ident, $batch:ident, $PortX:ident) => {
+        $batch.$write::<<$PortX<Pin0> as GpioPin>::Periph>()
+    };
+
+    (@nointr $write:ident, $batch:ident, $PortX:ident) => {};
+}
+
+gpio_parts!(P1, P1Parts, P1BatchParts, Port1, intr => [
+    p1_0: P1_0, P1_0Proxy, Pin0, DIR0;
+    p1_1: P1_1, P1_1Proxy, Pin1, DIR1;
+    p1_2: P1_2, P1_2Proxy, Pin2, DIR2;
+    p1_3: P1_3, P1_3Proxy, Pin3, DIR3;
+    p1_4: P1_4, P1_4Proxy, Pin4, DIR4;
+    p1_5: P1_5, P1_5Proxy, Pin5, DIR5;
+    p1_6: P1_6, P1_6Proxy, Pin6, DIR6;
+    p1_7: P1_7, P1_7Proxy, Pin7, DIR7;
+]);
+
+gpio_parts!(P2, P2Parts, P2BatchParts, Port2, intr => [
+    p2_0: P2_0, P2_0Proxy, Pin0, DIR0;
+    p2_1: P2_1, P2_1Proxy, Pin1, DIR1;
+    p2_2: P2_2, P2_2Proxy, Pin2, DIR2;
+    p2_3: P2_3, P2_3Proxy, Pin3, DIR3;
+    p2_4: P2_4, P2_4Proxy, Pin4, DIR4;
+    p2_5: P2_5, P2_5Proxy, Pin5, DIR5;
+    p2_6: P2_6, P2_6Proxy, Pin6, DIR6;
+    p2_7: P2_7, P2_7Proxy, Pin7, DIR7;
+]);
+
+gpio_parts!(P3, P3Parts, P3BatchParts, Port3, intr => [
+    p3_0: P3_0, P3_0Proxy, Pin0, DIR0;
+    p3_1: P3_1, P3_1Proxy, Pin1, DIR1;
+    p3_2: P3_2, P3_2Proxy, Pin2, DIR2;
+    p3_3: P3_3, P3_3Proxy, Pin3, DIR3;
+    p3_4: P3_4, P3_4Proxy, Pin4, DIR4;
+    p3_5: P3_5, P3_5Proxy, Pin5, DIR5;
+    p3_6: P3_6, P3_6Proxy, Pin6, DIR6;
+    p3_7: P3_7, P3_7Proxy, Pin7, DIR7;
+]);
+
+gpio_parts!(P4, P4Parts, P4BatchParts, Port4, intr => [
+    p4_0: P4_0, P4_0Proxy, Pin0, DIR0;
+    p4_1: P4_1, P4_1Proxy, Pin1, DIR1;
+    p4_2: P4_2, P4_2Proxy, Pin2, DIR2;
+    p4_3: P4_3, P4_3Proxy, Pin3, DIR3;
+    p4_4: P4_4, P4_4Proxy, Pin4, DIR4;
+    p4_5: P4_5, P4_5Proxy, Pin5, DIR5;
+    p4_6: P4_6, P4_6Proxy, Pin6, DIR6;
+    p4_7: P4_7, P4_7Proxy, Pin7, DIR7;
+]);
+
+gpio_parts!(P5, P5Parts, P5BatchParts, Port5, nointr => [
+    p5_0: P5_0, P5_0Proxy, Pin0, DIR0;
+    p5_1: P5_1, P5_1Proxy, Pin1, DIR1;
+    p5_2: P5_2, P5_2Proxy, Pin2, DIR2;
+    p5_3: P5_3, P5_3Proxy, Pin3, DIR3;
+    p5_4: P5_4, P5_4Proxy, Pin4, DIR4;
+]);
+
+gpio_parts!(P6, P6Parts, P6BatchParts, Port6, nointr => [
+    p6_0: P6_0, P6_0Proxy, Pin0, DIR0;
+    p6_1: P6_1, P6_1Proxy, Pin1, DIR1;
+    p6_2: P6_2, P6_2Proxy, Pin2, DIR2;
+    p6_3: P6_3, P6_3Proxy, Pin3, DIR3;
+    p6_4: P6_4, P6_4Proxy, Pin4, DIR4;
+    p6_5: P6_5, P6_5Proxy, Pin5, DIR5;
+    p6_6: P6_6, P6_6Proxy, Pin6, DIR6;
+]);
+
+type Handler = Mutex<Cell<Option<fn()>>>;
+
+// Calls the handler registered to each pin with a pending interrupt, in priority order. Reading
+// PxIV clears the flag of the pin it reports, so this should be called from the PORTx vector.
+// Meant to live in a plain static, shared between main and the vector.
+pub struct IntrDispatcher<P> {
+    handlers: [Handler; 8],
+    _periph: PhantomData<fn() -> P>,
+}
+
+pub type P1Dispatcher = IntrDispatcher<pac::p1::RegisterBlock>;
+pub type P2Dispatcher = IntrDispatcher<pac::p2::RegisterBlock>;
+pub type P3Dispatcher = IntrDispatcher<pac::p3::RegisterBlock>;
+pub type P4Dispatcher = IntrDispatcher<pac::p4::RegisterBlock>;
+
+// Host tests can't turn interrupts off, and the handler table is only ever touched from one
+// thread there anyway
+fn free<R>(f: impl FnOnce(&CriticalSection) -> R) -> R {
+    #[cfg(not(test))]
+    return interrupt::free(f);
+    #[cfg(test)]
+    f(unsafe { &CriticalSection::new() })
+}
+
+impl<P> IntrDispatcher<P> {
+    pub const fn new() -> Self {
+        IntrDispatcher {
+            handlers: [
+                Mutex::new(Cell::new(None)),
+                Mutex::new(Cell::new(None)),
+                Mutex::new(Cell::new(None)),
+                Mutex::new(Cell::new(None)),
+                Mutex::new(Cell::new(None)),
+                Mutex::new(Cell::new(None)),
+                Mutex::new(Cell::new(None)),
+                Mutex::new(Cell::new(None)),
+            ],
+            _periph: PhantomData,
+        }
     }
 }
-impl<INTR, LOCK> WritePren for P1_1Proxy<Input<Pulldown, INTR>, LOCK> {
-    fn pren_on(&self) -> bool {
-        true
+
+impl<P> Default for IntrDispatcher<P> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-impl<PULL, LOCK> P1_1Proxy<Input<PULL, Disabled>, LOCK> {
-    pub fn pulldown(self) -> P1_1Proxy<Input<Pulldown, Disabled>, LOCK> {
-        make_proxy!(P1_1Proxy)
+impl<P: IntrPeriph> IntrDispatcher<P> {
+    pub fn register<PIN: GpioPin<Periph = P>, DIR, LOCK>(
+        &self,
+        _pin: &Pin<PIN, DIR, LOCK>,
+        handler: fn(),
+    ) {
+        free(|cs| {
+            self.handlers[PIN::pin() as usize]
+                .borrow(cs)
+                .set(Some(handler))
+        });
+    }
+
+    pub fn unregister<PIN: GpioPin<Periph = P>, DIR, LOCK>(&self, _pin: &Pin<PIN, DIR, LOCK>) {
+        free(|cs| self.handlers[PIN::pin() as usize].borrow(cs).set(None));
+    }
+
+    pub fn dispatch(&self) {
+        let periph = unsafe { P::steal() };
+        loop {
+            match periph.pxiv().rd() {
+                0 => break,
+                // PxIV is 2 * (pin + 1) for the pending pin, so anything else is ignored
+                iv => {
+                    let slot = self.handlers.get((iv as usize >> 1).wrapping_sub(1));
+                    // The handler runs outside the critical section
+                    let handler = slot.and_then(|slot| free(|cs| slot.borrow(cs).get()));
+                    if let Some(handler) = handler {
+                        handler();
+                    }
+                }
+            }
+        }
     }
+}
+
+// Erased pins start here
+
+fn pin_in<P: GpioPeriph>(pin: u8) -> bool {
+    unsafe { P::steal() }.pxin().rd() & (1 << pin) != 0
+}
+
+fn pin_out<P: GpioPeriph>(pin: u8) -> bool {
+    unsafe { P::steal() }.pxout().rd() & (1 << pin) != 0
+}
+
+fn pin_set<P: GpioPeriph>(pin: u8) {
+    unsafe { P::steal() }.pxout().set(1 << pin);
+}
+
+fn pin_clear<P: GpioPeriph>(pin: u8) {
+    unsafe { P::steal() }.pxout().clear(1 << pin);
+}
+
+fn pin_toggle<P: GpioPeriph>(pin: u8) {
+    unsafe { P::steal() }.pxout().toggle(1 << pin);
+}
+
+// Pin with its number erased, so pins of the same port and direction have the same type
+pub struct PxN<P, DIR> {
+    pin: u8,
+    _periph: PhantomData<P>,
+    _dir: PhantomData<DIR>,
+}
 
-    pub fn pullup(self) -> P1_1Proxy<Input<Pullup, Disabled>, LOCK> {
-        make_proxy!(P1_1Proxy)
+impl<P: GpioPeriph, DIR> PxN<P, DIR> {
+    pub fn pin(&self) -> u8 {
+        self.pin
     }
 
-    pub fn float(self) -> P1_1Proxy<Input<Floating, Disabled>, LOCK> {
-        make_proxy!(P1_1Proxy)
+    pub fn downgrade(self) -> ErasedPin<DIR> {
+        ErasedPin {
+            port: P::port(),
+            pin: self.pin,
+            _dir: PhantomData,
+        }
     }
 }
 
-impl<DIR: ConvertToInput, LOCK> P1_1Proxy<DIR, LOCK> {
-    pub fn to_input(self) -> P1_1Proxy<Input<Unknown, Disabled>, LOCK> {
-        make_proxy!(P1_1Proxy)
+impl<P: GpioPeriph, PULL: Known, INTR> InputPin for PxN<P, Input<PULL, INTR>> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(pin_in::<P>(self.pin))
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.is_high().map(|b| !b)
     }
 }
 
-impl<DIR: ConvertToOutput, LOCK> P1_1Proxy<DIR, LOCK> {
-    pub fn to_output(self) -> P1_1Proxy<Output<NoOutToken>, LOCK> {
-        make_proxy!(P1_1Proxy, false)
+impl<'out, P: GpioPeriph> OutputPin for PxN<P, Output<OutToken<'out>>> {
+    type Error = Infallible;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        pin_set::<P>(self.pin);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        pin_clear::<P>(self.pin);
+        Ok(())
     }
 }
 
-impl P1_1Proxy<Output<NoOutToken>, Unlocked> {
-    pub fn on(self) -> P1_1Proxy<Output<NoOutToken>, Unlocked> {
-        make_proxy!(P1_1Proxy, true)
+impl<'out, P: GpioPeriph> StatefulOutputPin for PxN<P, Output<OutToken<'out>>> {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(pin_out::<P>(self.pin))
     }
 
-    pub fn off(self) -> P1_1Proxy<Output<NoOutToken>, Unlocked> {
-        make_proxy!(P1_1Proxy, false)
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        self.is_set_high().map(|b| !b)
     }
 }
 
-pub struct BatchParts<DIR0, DIR1, LOCK> {
-    pub p1_0: P1_0Proxy<DIR0, LOCK>,
-    pub p1_1: P1_1Proxy<DIR1, LOCK>,
+impl<'out, P: GpioPeriph> ToggleableOutputPin for PxN<P, Output<OutToken<'out>>> {
+    type Error = Infallible;
+
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        pin_toggle::<P>(self.pin);
+        Ok(())
+    }
 }
 
-impl<DIR0, DIR1, LOCK> Parts<DIR0, DIR1, LOCK> {
-    pub fn batch(self) -> BatchParts<DIR0, DIR1, LOCK> {
-        BatchParts {
-            p1_0: make_proxy!(P1_0Proxy),
-            p1_1: make_proxy!(P1_1Proxy),
+// Picks the register block matching a runtime port number
+macro_rules! on_port {
+    ($port:expr, $f:ident($($arg:expr),*)) => {
+        match $port {
+            PortNum::P1 => $f::<pac::p1::RegisterBlock>($($arg),*),
+            PortNum::P2 => $f::<pac::p2::RegisterBlock>($($arg),*),
+            PortNum::P3 => $f::<pac::p3::RegisterBlock>($($arg),*),
+            PortNum::P4 => $f::<pac::p4::RegisterBlock>($($arg),*),
+            PortNum::P5 => $f::<pac::p5::RegisterBlock>($($arg),*),
+            PortNum::P6 => $f::<pac::p6::RegisterBlock>($($arg),*),
         }
+    };
+}
+
+// Pin with both its port and number erased
+pub struct ErasedPin<DIR> {
+    port: PortNum,
+    pin: u8,
+    _dir: PhantomData<DIR>,
+}
+
+impl<DIR> ErasedPin<DIR> {
+    pub fn port(&self) -> PortNum {
+        self.port
+    }
+
+    pub fn pin(&self) -> u8 {
+        self.pin
+    }
+}
+
+impl<PULL: Known, INTR> InputPin for ErasedPin<Input<PULL, INTR>> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(on_port!(self.port, pin_in(self.pin)))
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.is_high().map(|b| !b)
+    }
+}
+
+impl<'out> OutputPin for ErasedPin<Output<OutToken<'out>>> {
+    type Error = Infallible;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        on_port!(self.port, pin_set(self.pin));
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        on_port!(self.port, pin_clear(self.pin));
+        Ok(())
     }
 }
 
-impl<DIR0, DIR1, LOCK> BatchParts<DIR0, DIR1, LOCK> {
-    pub fn write(self) -> Parts<DIR0, DIR1, LOCK> {
-        let mut pdir: u8 = 0;
-        let mut pout: u8 = 0;
-        let mut pren: u8 = 0;
+impl<'out> StatefulOutputPin for ErasedPin<Output<OutToken<'out>>> {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(on_port!(self.port, pin_out(self.pin)))
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        self.is_set_high().map(|b| !b)
+    }
+}
 
-        pdir |= self.p1_0.pdir_on() as u8;
-        pout |= self.p1_0.pout_on() as u8;
-        pren |= self.p1_0.pren_on() as u8;
+impl<'out> ToggleableOutputPin for ErasedPin<Output<OutToken<'out>>> {
+    type Error = Infallible;
 
-        pdir |= (self.p1_1.pdir_on() as u8) << 1;
-        pout |= (self.p1_1.pout_on() as u8) << 1;
-        pren |= (self.p1_1.pren_on() as u8) << 1;
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        on_port!(self.port, pin_toggle(self.pin));
+        Ok(())
+    }
+}
 
-        let p1 = unsafe { &*pac::P1::ptr() };
-        p1.p1dir.write(|w| unsafe { w.bits(pdir) });
-        p1.p1out.write(|w| unsafe { w.bits(pout) });
-        p1.p1ren.write(|w| unsafe { w.bits(pren) });
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regs::mock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn check_mask<PIN: GpioPin>(port: PortNum, pin: u8) -> u8 {
+        assert_eq!(<PIN::Periph as GpioPeriph>::port(), port);
+        assert_eq!(PIN::pin(), pin);
+        assert_eq!(PIN::mask(), 1 << pin);
+        PIN::mask()
+    }
+
+    // Walks the pin through both directions and every output operation, starting from a few
+    // register patterns, and checks that only its own PxDIR and PxOUT bits ever change
+    fn check_pin_bits<PIN: GpioPin>() {
+        let periph = unsafe { PIN::Periph::steal() };
+        let mask = PIN::mask();
+        let pdir = PDIR(());
+        let pout = POUT(());
+        for &base in &[0x00, 0xFF, 0b1010_0101, 0b0101_1010] {
+            periph.pxdir().wr(base);
+            periph.pxout().wr(base);
+
+            let pin: Pin<PIN, Unknown, Unlocked> = make_periph!(Pin);
+            let mut pin = pin.to_output(&pdir).enable(&pout);
+            assert_eq!(periph.pxdir().rd(), base | mask);
+            assert_eq!(periph.pxout().rd(), base);
+
+            pin.set_high().unwrap();
+            assert_eq!(periph.pxout().rd(), base | mask);
+            assert!(pin.is_set_high().unwrap());
+            pin.set_low().unwrap();
+            assert_eq!(periph.pxout().rd(), base & !mask);
+            assert!(pin.is_set_low().unwrap());
+            pin.toggle().unwrap();
+            assert_eq!(periph.pxout().rd(), base | mask);
+            pin.toggle().unwrap();
+            assert_eq!(periph.pxout().rd(), base & !mask);
+
+            pin.to_input(&pdir);
+            assert_eq!(periph.pxdir().rd(), base & !mask);
+            assert_eq!(periph.pxout().rd(), base & !mask);
+        }
+    }
 
-        Parts {
-            psel: PSEL(()),
-            pout: POUT(()),
-            p1_0: make_periph!(P1_0),
-            p1_1: make_periph!(P1_1),
+    macro_rules! port_test {
+        ($name:ident, $Px:ident, $all:expr => [$($Pin:ident: $i:expr),+]) => {
+            #[test]
+            fn $name() {
+                mock::reset();
+                let mut seen = 0u8;
+                $(
+                    let mask = check_mask::<$Pin<Unknown, Locked>>(PortNum::$Px, $i);
+                    assert_eq!(seen & mask, 0);
+                    seen |= mask;
+
+                    let pin: $Pin<Unknown, Unlocked> = make_periph!(Pin);
+                    let erased = pin.downgrade();
+                    assert_eq!(erased.pin(), $i);
+                    let erased = erased.downgrade();
+                    assert_eq!(erased.port(), PortNum::$Px);
+                    assert_eq!(erased.pin(), $i);
+
+                    check_pin_bits::<$Pin<Unknown, Locked>>();
+                )+
+                assert_eq!(seen, $all);
+            }
+        };
+    }
+
+    port_test!(p1_masks, P1, 0xFF => [P1_0: 0, P1_1: 1, P1_2: 2, P1_3: 3, P1_4: 4, P1_5: 5, P1_6: 6, P1_7: 7]);
+    port_test!(p2_masks, P2, 0xFF => [P2_0: 0, P2_1: 1, P2_2: 2, P2_3: 3, P2_4: 4, P2_5: 5, P2_6: 6, P2_7: 7]);
+    port_test!(p3_masks, P3, 0xFF => [P3_0: 0, P3_1: 1, P3_2: 2, P3_3: 3, P3_4: 4, P3_5: 5, P3_6: 6, P3_7: 7]);
+    port_test!(p4_masks, P4, 0xFF => [P4_0: 0, P4_1: 1, P4_2: 2, P4_3: 3, P4_4: 4, P4_5: 5, P4_6: 6, P4_7: 7]);
+    port_test!(p5_masks, P5, 0x1F => [P5_0: 0, P5_1: 1, P5_2: 2, P5_3: 3, P5_4: 4]);
+    port_test!(p6_masks, P6, 0x7F => [P6_0: 0, P6_1: 1, P6_2: 2, P6_3: 3, P6_4: 4, P6_5: 5, P6_6: 6]);
+
+    fn p1() -> &'static pac::p1::RegisterBlock {
+        unsafe { pac::p1::RegisterBlock::steal() }
+    }
+
+    #[test]
+    fn port_masked_writes() {
+        mock::reset();
+        let mut port: P1<Output<NoOutToken>, Unlocked> = make_periph!(Port);
+        port.write(0b1010_0101);
+        port.toggle();
+        assert_eq!(p1().pxout().rd(), 0b0101_1010);
+
+        port.set_mask(0b1000_0001);
+        port.clear_mask(0b0100_0000);
+        port.toggle_mask(0b0000_0011);
+        assert_eq!(p1().pxout().rd(), 0b1001_1000);
+
+        port.write_masked(0xF0, 0x3C);
+        assert_eq!(p1().pxout().rd(), 0b0011_1000);
+    }
+
+    #[test]
+    fn pin_output() {
+        mock::reset();
+        let pout = POUT(());
+        let pin: P1_1<Output<NoOutToken>, Unlocked> = make_periph!(Pin);
+        let mut pin = pin.enable(&pout);
+
+        pin.set_high().unwrap();
+        assert_eq!(p1().pxout().rd(), 0b10);
+        assert!(pin.is_set_high().unwrap());
+        pin.toggle().unwrap();
+        assert_eq!(p1().pxout().rd(), 0b00);
+        p1().pxout().wr(0xFF);
+        pin.set_low().unwrap();
+        assert_eq!(p1().pxout().rd(), 0b1111_1101);
+    }
+
+    static P1_INTR: P1Dispatcher = P1Dispatcher::new();
+    static P1_2_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn p1iv() -> *mut u16 {
+        p1().pxiv() as *const _ as *mut u16
+    }
+
+    // Stands in for the hardware clearing the flag on the PxIV read
+    fn on_p1_2() {
+        P1_2_CALLS.fetch_add(1, Ordering::Relaxed);
+        unsafe { p1iv().write(0) };
+    }
+
+    #[test]
+    fn dispatch() {
+        mock::reset();
+        let pin: P1_2<Input<Pullup, Enabled>, Unlocked> = make_periph!(Pin);
+        P1_INTR.register(&pin, on_p1_2);
+
+        unsafe { p1iv().write(0x06) };
+        P1_INTR.dispatch();
+        assert_eq!(P1_2_CALLS.load(Ordering::Relaxed), 1);
+        P1_INTR.dispatch();
+        assert_eq!(P1_2_CALLS.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn batch_preserves_other_pins() {
+        mock::reset();
+        p1().pxdir().wr(0b0000_0001);
+        p1().pxout().wr(0b0000_0001);
+        p1().pxsel0().wr(0b0000_1000);
+
+        let port: P1<Unknown, Unlocked> = make_periph!(Port);
+        let batch = port.split().batch();
+        P1BatchParts {
+            p1_0: batch.p1_0,
+            p1_1: batch.p1_1.to_output().on(),
+            p1_2: batch.p1_2.to_output().alternate2(),
+            p1_3: batch.p1_3.alternate2(),
+            p1_4: batch.p1_4.to_input().pullup().enable_intr_falling_edge(),
+            // The output level has to survive the switch to the alternate function
+            p1_5: batch.p1_5.to_output().on().alternate1(),
+            p1_6: batch.p1_6,
+            p1_7: batch.p1_7,
+        }
+        .write();
+
+        assert_eq!(p1().pxdir().rd(), 0b0010_0111);
+        assert_eq!(p1().pxout().rd(), 0b0011_0011);
+        assert_eq!(p1().pxren().rd(), 0b0001_0000);
+        // P1.3 moves from function 1 to function 2 in one PxSELC write
+        assert_eq!(p1().pxsel0().rd(), 0b0010_0000);
+        assert_eq!(p1().pxsel1().rd(), 0b0000_1100);
+        assert_eq!(p1().pxies().rd(), 0b0001_0000);
+        assert_eq!(p1().pxie().rd(), 0b0001_0000);
+    }
+    #[test]
+    fn batch_remux_keeps_level() {
+        mock::reset();
+        p1().pxdir().wr(0b0000_0010);
+        p1().pxout().wr(0b0000_0010);
+
+        let port: P1<Output<NoOutToken>, Unlocked> = make_periph!(Port);
+        let batch = port.split().batch();
+        P1BatchParts {
+            p1_0: batch.p1_0,
+            p1_1: batch.p1_1.alternate1(),
+            p1_2: batch.p1_2,
+            p1_3: batch.p1_3,
+            p1_4: batch.p1_4,
+            p1_5: batch.p1_5,
+            p1_6: batch.p1_6,
+            p1_7: batch.p1_7,
         }
+        .write();
+
+        // Only the select bits of a re-muxed pin get written, so its output stays high
+        assert_eq!(p1().pxout().rd(), 0b0000_0010);
+        assert_eq!(p1().pxdir().rd(), 0b0000_0010);
+        assert_eq!(p1().pxsel0().rd(), 0b0000_0010);
+        assert_eq!(p1().pxsel1().rd(), 0);
     }
 }
@@ -5,80 +5,137 @@ use pac::generic::{Readable, Reg, Writable};
 type Reg8<T> = Reg<u8, T>;
 type Reg16<T> = Reg<u16, T>;
 
+pub trait RegRead<U> {
+    fn rd(&self) -> U;
+}
+
+// Masked operations compile down to single bis/bic/xor instructions, so they can't clobber bits
+// that hardware sets in between
+pub trait RegRw8: RegRead<u8> {
+    fn wr(&self, bits: u8);
+    fn set(&self, mask: u8);
+    fn clear(&self, mask: u8);
+    fn toggle(&self, mask: u8);
+}
+
+impl<T> RegRead<u8> for Reg8<T>
+where
+    Reg8<T>: Readable,
+{
+    #[inline(always)]
+    fn rd(&self) -> u8 {
+        self.read().bits()
+    }
+}
+
+impl<T> RegRead<u16> for Reg16<T>
+where
+    Reg16<T>: Readable,
+{
+    #[inline(always)]
+    fn rd(&self) -> u16 {
+        self.read().bits()
+    }
+}
+
+impl<T> RegRw8 for Reg8<T>
+where
+    Reg8<T>: Readable + Writable,
+{
+    #[inline(always)]
+    fn wr(&self, bits: u8) {
+        self.write_with_zero(|w| unsafe { w.bits(bits) });
+    }
+
+    #[inline(always)]
+    fn set(&self, mask: u8) {
+        unsafe { self.set_bits(|w| w.bits(mask)) };
+    }
+
+    #[inline(always)]
+    fn clear(&self, mask: u8) {
+        unsafe { self.clear_bits(|w| w.bits(!mask)) };
+    }
+
+    #[inline(always)]
+    fn toggle(&self, mask: u8) {
+        unsafe { self.toggle_bits(|w| w.bits(mask)) };
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PortNum {
+    P1,
+    P2,
+    P3,
+    P4,
+    P5,
+    P6,
+}
+
 pub trait GpioPeriph {
-    type Pxin;
-    type Pxout;
-    type Pxdir;
-    type Pxren;
-    type Pxselc;
-    type Pxsel0;
-    type Pxsel1;
-
-    fn pxin(&self) -> &Reg8<Self::Pxin>
-    where
-        Reg8<Self::Pxin>: Readable;
-
-    fn pxout(&self) -> &Reg8<Self::Pxout>
-    where
-        Reg8<Self::Pxout>: Readable + Writable;
-
-    fn pxdir(&self) -> &Reg8<Self::Pxdir>
-    where
-        Reg8<Self::Pxout>: Readable + Writable;
-
-    fn pxren(&self) -> &Reg8<Self::Pxren>
-    where
-        Reg8<Self::Pxout>: Readable + Writable;
-
-    fn pxselc(&self) -> &Reg8<Self::Pxselc>
-    where
-        Reg8<Self::Pxout>: Readable + Writable;
-
-    fn pxsel0(&self) -> &Reg8<Self::Pxsel0>
-    where
-        Reg8<Self::Pxout>: Readable + Writable;
-
-    fn pxsel1(&self) -> &Reg8<Self::Pxsel1>
-    where
-        Reg8<Self::Pxout>: Readable + Writable;
+    type Pxin: RegRead<u8>;
+    type Pxout: RegRw8;
+    type Pxdir: RegRw8;
+    type Pxren: RegRw8;
+    type Pxselc: RegRw8;
+    type Pxsel0: RegRw8;
+    type Pxsel1: RegRw8;
+
+    /// # Safety
+    /// Only for use by types that already have exclusive access to the parts of the port they touch
+    unsafe fn steal<'a>() -> &'a Self;
+
+    fn port() -> PortNum;
+
+    fn pxin(&self) -> &Self::Pxin;
+
+    fn pxout(&self) -> &Self::Pxout;
+
+    fn pxdir(&self) -> &Self::Pxdir;
+
+    fn pxren(&self) -> &Self::Pxren;
+
+    fn pxselc(&self) -> &Self::Pxselc;
+
+    fn pxsel0(&self) -> &Self::Pxsel0;
+
+    fn pxsel1(&self) -> &Self::Pxsel1;
 }
 
-trait IntrPeriph: GpioPeriph {
-    type Pxies;
-    type Pxie;
-    type Pxifg;
-    type Pxiv;
+pub trait IntrPeriph: GpioPeriph {
+    type Pxies: RegRw8;
+    type Pxie: RegRw8;
+    type Pxifg: RegRw8;
+    type Pxiv: RegRead<u16>;
 
-    fn pxies(&self) -> &Reg8<Self::Pxies>
-    where
-        Reg8<Self::Pxout>: Readable + Writable;
+    fn pxies(&self) -> &Self::Pxies;
 
-    fn pxie(&self) -> &Reg8<Self::Pxie>
-    where
-        Reg8<Self::Pxout>: Readable + Writable;
+    fn pxie(&self) -> &Self::Pxie;
 
-    fn pxifg(&self) -> &Reg8<Self::Pxifg>
-    where
-        Reg8<Self::Pxout>: Readable + Writable;
+    fn pxifg(&self) -> &Self::Pxifg;
 
-    fn pxiv(&self) -> &Reg16<Self::Pxiv>
-    where
-        Reg16<Self::Pxout>: Readable + Writable;
+    fn pxiv(&self) -> &Self::Pxiv;
 }
 
 pub trait GpioPin {
     type Periph: GpioPeriph;
 
     fn pin() -> u8;
+
+    // Every pin-level register access goes through this, so a pin can only ever touch its own bit
+    fn mask() -> u8 {
+        1 << Self::pin()
+    }
 }
 
-trait Number {
+pub trait Number {
     fn num() -> u8;
 }
-trait UnderSeven: Number {}
-trait UnderFive: Number {}
+pub trait UnderSeven: Number {}
+pub trait UnderFive: Number {}
 
-struct Pin0;
+pub struct Pin0;
 impl Number for Pin0 {
     fn num() -> u8 {
         0
@@ -87,7 +144,7 @@ impl Number for Pin0 {
 impl UnderSeven for Pin0 {}
 impl UnderFive for Pin0 {}
 
-struct Pin1;
+pub struct Pin1;
 impl Number for Pin1 {
     fn num() -> u8 {
         1
@@ -96,7 +153,7 @@ impl Number for Pin1 {
 impl UnderSeven for Pin1 {}
 impl UnderFive for Pin1 {}
 
-struct Pin2;
+pub struct Pin2;
 impl Number for Pin2 {
     fn num() -> u8 {
         2
@@ -105,7 +162,7 @@ impl Number for Pin2 {
 impl UnderSeven for Pin2 {}
 impl UnderFive for Pin2 {}
 
-struct Pin3;
+pub struct Pin3;
 impl Number for Pin3 {
     fn num() -> u8 {
         3
@@ -114,7 +171,7 @@ impl Number for Pin3 {
 impl UnderSeven for Pin3 {}
 impl UnderFive for Pin3 {}
 
-struct Pin4;
+pub struct Pin4;
 impl Number for Pin4 {
     fn num() -> u8 {
         4
@@ -123,7 +180,7 @@ impl Number for Pin4 {
 impl UnderSeven for Pin4 {}
 impl UnderFive for Pin4 {}
 
-struct Pin5;
+pub struct Pin5;
 impl Number for Pin5 {
     fn num() -> u8 {
         5
@@ -131,7 +188,7 @@ impl Number for Pin5 {
 }
 impl UnderSeven for Pin5 {}
 
-struct Pin6;
+pub struct Pin6;
 impl Number for Pin6 {
     fn num() -> u8 {
         6
@@ -139,61 +196,61 @@ impl Number for Pin6 {
 }
 impl UnderSeven for Pin6 {}
 
-struct Pin7;
+pub struct Pin7;
 impl Number for Pin7 {
     fn num() -> u8 {
         7
     }
 }
 
-struct Port1<P>(PhantomData<P>);
+pub struct Port1<P>(PhantomData<P>);
 impl<P: Number> GpioPin for Port1<P> {
-    type Periph = pac::P1;
+    type Periph = pac::p1::RegisterBlock;
 
     fn pin() -> u8 {
         P::num()
     }
 }
 
-struct Port2<P>(PhantomData<P>);
+pub struct Port2<P>(PhantomData<P>);
 impl<P: Number> GpioPin for Port2<P> {
-    type Periph = pac::P2;
+    type Periph = pac::p2::RegisterBlock;
 
     fn pin() -> u8 {
         P::num()
     }
 }
 
-struct Port3<P>(PhantomData<P>);
+pub struct Port3<P>(PhantomData<P>);
 impl<P: Number> GpioPin for Port3<P> {
-    type Periph = pac::P3;
+    type Periph = pac::p3::RegisterBlock;
 
     fn pin() -> u8 {
         P::num()
     }
 }
 
-struct Port4<P>(PhantomData<P>);
+pub struct Port4<P>(PhantomData<P>);
 impl<P: Number> GpioPin for Port4<P> {
-    type Periph = pac::P4;
+    type Periph = pac::p4::RegisterBlock;
 
     fn pin() -> u8 {
         P::num()
     }
 }
 
-struct Port5<P>(PhantomData<P>);
+pub struct Port5<P>(PhantomData<P>);
 impl<P: UnderFive> GpioPin for Port5<P> {
-    type Periph = pac::P5;
+    type Periph = pac::p5::RegisterBlock;
 
     fn pin() -> u8 {
         P::num()
     }
 }
 
-struct Port6<P>(PhantomData<P>);
+pub struct Port6<P>(PhantomData<P>);
 impl<P: UnderSeven> GpioPin for Port6<P> {
-    type Periph = pac::P6;
+    type Periph = pac::p6::RegisterBlock;
 
     fn pin() -> u8 {
         P::num()
@@ -208,102 +265,88 @@ macro_rules! gpio_impl {
     ) => {
         mod $px {
             use super::*;
+            #[cfg_attr(test, allow(unused_imports))]
             use pac::$px::{
-                $PxDIR,  $PxOUT, $PxREN, $PxSEL0, $PxSEL1, $PxSELC, $PxIN,
+                RegisterBlock, $PxDIR,  $PxOUT, $PxREN, $PxSEL0, $PxSEL1, $PxSELC, $PxIN,
                 $($PxIE, $PxIES, $PxIFG, $PxIV)?
             };
 
-            impl GpioPeriph for pac::$Px {
-                type Pxdir = $PxDIR;
-                type Pxin = $PxIN;
-                type Pxout = $PxOUT;
-                type Pxren = $PxREN;
-                type Pxsel0 = $PxSEL0;
-                type Pxsel1 = $PxSEL1;
-                type Pxselc = $PxSELC;
-
-                fn pxdir(&self) -> &Reg8<$PxDIR>
-                where
-                    Reg8<$PxDIR>: Readable + Writable,
-                {
+            impl GpioPeriph for RegisterBlock {
+                type Pxdir = Reg8<$PxDIR>;
+                type Pxin = Reg8<$PxIN>;
+                type Pxout = Reg8<$PxOUT>;
+                type Pxren = Reg8<$PxREN>;
+                type Pxsel0 = Reg8<$PxSEL0>;
+                type Pxsel1 = Reg8<$PxSEL1>;
+                #[cfg(not(test))]
+                type Pxselc = Reg8<$PxSELC>;
+                #[cfg(test)]
+                type Pxselc = crate::regs::mock::Selc<Self>;
+
+                #[inline(always)]
+                unsafe fn steal<'a>() -> &'a Self {
+                    crate::regs::block(pac::$Px::ptr())
+                }
+
+                fn port() -> PortNum {
+                    PortNum::$Px
+                }
+
+                fn pxdir(&self) -> &Reg8<$PxDIR> {
                     &self.$pxdir
                 }
 
-                fn pxin(&self) -> &Reg8<$PxIN>
-                where
-                    Reg8<$PxIN>: Readable,
-                {
+                fn pxin(&self) -> &Reg8<$PxIN> {
                     &self.$pxin
                 }
 
-                fn pxout(&self) -> &Reg8<$PxOUT>
-                where
-                    Reg8<$PxOUT>: Readable + Writable,
-                {
+                fn pxout(&self) -> &Reg8<$PxOUT> {
                     &self.$pxout
                 }
 
-                fn pxren(&self) -> &Reg8<$PxREN>
-                where
-                    Reg8<$PxREN>: Readable + Writable,
-                {
+                fn pxren(&self) -> &Reg8<$PxREN> {
                     &self.$pxren
                 }
 
-                fn pxsel0(&self) -> &Reg8<$PxSEL0>
-                where
-                    Reg8<$PxSEL0>: Readable + Writable,
-                {
+                fn pxsel0(&self) -> &Reg8<$PxSEL0> {
                     &self.$pxsel0
                 }
 
-                fn pxsel1(&self) -> &Reg8<$PxSEL1>
-                where
-                    Reg8<$PxSEL1>: Readable + Writable,
-                {
+                fn pxsel1(&self) -> &Reg8<$PxSEL1> {
                     &self.$pxsel1
                 }
 
-                fn pxselc(&self) -> &Reg8<$PxSELC>
-                where
-                    Reg8<$PxSELC>: Readable + Writable,
-                {
+                #[cfg(not(test))]
+                fn pxselc(&self) -> &Reg8<$PxSELC> {
                     &self.$pxselc
                 }
+
+                #[cfg(test)]
+                fn pxselc(&self) -> &Self::Pxselc {
+                    crate::regs::mock::Selc::new(self)
+                }
             }
 
             $(
-                impl IntrPeriph for pac::$Px {
-                    type Pxie = $PxIE;
-                    type Pxies = $PxIES;
-                    type Pxifg = $PxIFG;
-                    type Pxiv = $PxIV;
-
-                    fn pxie(&self) -> &Reg8<$PxIE>
-                    where
-                        Reg8<$PxIE>: Readable + Writable,
-                    {
+                impl IntrPeriph for RegisterBlock {
+                    type Pxie = Reg8<$PxIE>;
+                    type Pxies = Reg8<$PxIES>;
+                    type Pxifg = Reg8<$PxIFG>;
+                    type Pxiv = Reg16<$PxIV>;
+
+                    fn pxie(&self) -> &Reg8<$PxIE> {
                         &self.$pxie
                     }
 
-                    fn pxies(&self) -> &Reg8<$PxIES>
-                    where
-                        Reg8<$PxIES>: Readable + Writable,
-                    {
+                    fn pxies(&self) -> &Reg8<$PxIES> {
                         &self.$pxies
                     }
 
-                    fn pxifg(&self) -> &Reg8<$PxIFG>
-                    where
-                        Reg8<$PxIFG>: Readable + Writable,
-                    {
+                    fn pxifg(&self) -> &Reg8<$PxIFG> {
                         &self.$pxifg
                     }
 
-                    fn pxiv(&self) -> &Reg16<$PxIV>
-                    where
-                        Reg16<$PxIV>: Readable,
-                    {
+                    fn pxiv(&self) -> &Reg16<$PxIV> {
                         &self.$pxiv
                     }
                 }
@@ -1,10 +1,13 @@
-#![no_std]
-#![feature(specialization)]
+#![cfg_attr(not(test), no_std)]
+#![feature(specialization, const_fn_trait_bound, const_fn_fn_ptr_basics)]
+#![allow(incomplete_features, clippy::return_self_not_must_use)]
 
 pub mod clocks;
 pub mod gpio;
 #[allow(dead_code)]
 pub mod gpio_trait;
+mod regs;
 pub mod serial;
+pub mod time;
 pub mod timer;
 pub mod watchdog;
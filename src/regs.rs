@@ -0,0 +1,120 @@
+use msp430fr2355 as pac;
+
+// Register blocks of the PAC peripherals. Everything goes through here instead of `ptr()` so that
+// host tests can back the registers with plain memory.
+pub(crate) trait Regs {
+    type Block: 'static;
+
+    /// # Safety
+    /// Aliases the peripheral's registers whether or not the caller owns the peripheral
+    unsafe fn steal<'a>() -> &'a Self::Block;
+
+    fn regs(&self) -> &Self::Block {
+        unsafe { Self::steal() }
+    }
+}
+
+pub(crate) unsafe fn block<'a, T>(ptr: *const T) -> &'a T {
+    #[cfg(test)]
+    let ptr = mock::block(ptr);
+    &*ptr
+}
+
+macro_rules! regs {
+    ($($P:ident: $p:ident),+) => {
+        $(
+            impl Regs for pac::$P {
+                type Block = pac::$p::RegisterBlock;
+
+                unsafe fn steal<'a>() -> &'a Self::Block {
+                    block(pac::$P::ptr())
+                }
+            }
+        )+
+    };
+}
+
+regs!(
+    PMM: pmm,
+    SFR: sfr,
+    CS: cs,
+    FRCTL: frctl,
+    WDT_A: wdt_a,
+    TB0: tb0,
+    E_USCI_A0: e_usci_a0,
+    E_USCI_A1: e_usci_a1
+);
+
+// In-memory register file. Each thread gets its own zeroed copy of every register block, created
+// the first time the block is touched. Tests can end up sharing a thread, so every test that
+// touches registers starts with reset().
+#[cfg(test)]
+pub(crate) mod mock {
+    use crate::gpio_trait::{GpioPeriph, RegRead, RegRw8};
+    use std::cell::RefCell;
+    use std::mem::size_of;
+    use std::vec::Vec;
+
+    thread_local! {
+        // (MMIO address, host address) of every block touched so far
+        static BLOCKS: RefCell<Vec<(usize, usize)>> = RefCell::new(Vec::new());
+    }
+
+    // Forgets every block, so the next access hands out fresh zeroed memory. The old memory is
+    // leaked, since references from before the reset can still point at it.
+    pub fn reset() {
+        BLOCKS.with(|blocks| blocks.borrow_mut().clear());
+    }
+
+    pub fn block<T>(ptr: *const T) -> *const T {
+        BLOCKS.with(|blocks| {
+            let mut blocks = blocks.borrow_mut();
+            let addr = ptr as usize;
+            match blocks.iter().find(|(mmio, _)| *mmio == addr) {
+                Some((_, host)) => *host as *const T,
+                None => {
+                    // Registers are at most 16 bits wide, so u16 storage is aligned enough
+                    let mem = vec![0u16; (size_of::<T>() + 1) / 2].into_boxed_slice();
+                    let host = Box::leak(mem).as_ptr() as usize;
+                    blocks.push((addr, host));
+                    host as *const T
+                }
+            }
+        })
+    }
+
+    // PxSELC isn't a plain register: a 1 written to it complements that bit in both PxSEL0 and
+    // PxSEL1. Under test it's handed out as a view of the whole port block so writes can do that.
+    #[repr(transparent)]
+    pub struct Selc<P>(P);
+
+    impl<P> Selc<P> {
+        pub fn new(periph: &P) -> &Self {
+            unsafe { &*(periph as *const P as *const Self) }
+        }
+    }
+
+    impl<P: GpioPeriph> RegRead<u8> for Selc<P> {
+        // Reads back as 0
+        fn rd(&self) -> u8 {
+            0
+        }
+    }
+
+    impl<P: GpioPeriph> RegRw8 for Selc<P> {
+        fn wr(&self, bits: u8) {
+            self.0.pxsel0().toggle(bits);
+            self.0.pxsel1().toggle(bits);
+        }
+
+        fn set(&self, mask: u8) {
+            self.wr(mask);
+        }
+
+        fn clear(&self, _mask: u8) {}
+
+        fn toggle(&self, mask: u8) {
+            self.wr(mask);
+        }
+    }
+}
@@ -1,107 +1,358 @@
 use crate::clocks::{Aclk, Clock, Smclk};
+use crate::gpio::{Alternate1, Unlocked, P1_6, P1_7, P4_2, P4_3};
+use crate::regs::Regs;
+use crate::time::{Bps, Hertz};
 use msp430fr2355 as pac;
 
-use pac::e_usci_a1::uca1ctlw0::{UC7BIT_A, UCMSB_A, UCSPB_A, UCSSEL_A};
-use pac::E_USCI_A1;
+use core::cell::UnsafeCell;
+use core::cmp::{max, min};
+use core::convert::{Infallible, TryInto};
+use core::fmt;
+use core::marker::PhantomData;
+use core::sync::atomic::{compiler_fence, Ordering};
+use embedded_hal::blocking::serial::{write as serial_write, Write as _};
+use embedded_hal::serial;
+use pac::{E_USCI_A0, E_USCI_A1};
 
-enum Parity {
-    Even,
-    Odd,
-    NoParity,
+// Pins that can carry the UART signals of a given eUSCI, once muxed to the right function
+pub trait TxPin<USCI> {}
+pub trait RxPin<USCI> {}
+
+impl<DIR> TxPin<E_USCI_A0> for P1_7<Alternate1<DIR>, Unlocked> {}
+impl<DIR> RxPin<E_USCI_A0> for P1_6<Alternate1<DIR>, Unlocked> {}
+impl<DIR> TxPin<E_USCI_A1> for P4_3<Alternate1<DIR>, Unlocked> {}
+impl<DIR> RxPin<E_USCI_A1> for P4_2<Alternate1<DIR>, Unlocked> {}
+
+// Keeps the register helpers and their types out of the public API, and stops other crates from
+// implementing SerialUsci
+mod sealed {
+    use super::Error;
+
+    // UCAxCTLW0 settings in UART mode. UCSWRST is left to the driver.
+    #[derive(Clone, Copy)]
+    pub struct UcaCtlw0 {
+        pub ucpen: bool,
+        pub ucpar: bool,
+        pub ucmsb: bool,
+        pub uc7bit: bool,
+        pub ucspb: bool,
+        pub ucssel: u8,
+        pub ucrxeie: bool,
+        pub ucbrkie: bool,
+    }
+
+    // The eUSCI_A instances have identical register layouts but separate PAC types, so the driver
+    // talks to them through this
+    pub trait UsciRegs {
+        fn ctlw0_reset();
+        fn ctlw0_wr(reg: &UcaCtlw0);
+        fn brw_wr(br: u16);
+        fn mctlw_wr(os16: bool, brs: u8, brf: u8);
+        fn txifg_rd() -> bool;
+        fn rxifg_rd() -> bool;
+        fn txbuf_wr(byte: u8);
+        fn rxbuf_rd() -> u8;
+        fn rx_err_rd() -> Option<Error>;
+        fn rx_err_clear();
+        fn overrun_rd() -> bool;
+        fn busy_rd() -> bool;
+        fn rxie_wr(on: bool);
+        fn txie_wr(on: bool);
+        fn txifg_set();
+        fn iv_rd() -> u16;
+    }
 }
 
+use sealed::{UcaCtlw0, UsciRegs};
+
+// eUSCI_A instances that can run as a UART
+pub trait SerialUsci: UsciRegs {}
+
+macro_rules! serial_usci {
+    ($USCI:ident => $ctlw0:ident, $brw:ident, $mctlw:ident, $ifg:ident, $txbuf:ident, $rxbuf:ident, $statw:ident, $ie:ident, $iv:ident) => {
+        impl UsciRegs for $USCI {
+            #[inline(always)]
+            fn ctlw0_reset() {
+                unsafe { Self::steal() }
+                    .$ctlw0()
+                    .write(|w| w.ucswrst().set_bit());
+            }
+
+            #[inline(always)]
+            fn ctlw0_wr(reg: &UcaCtlw0) {
+                unsafe { Self::steal() }.$ctlw0().write(|w| {
+                    w.ucpen()
+                        .bit(reg.ucpen)
+                        .ucpar()
+                        .bit(reg.ucpar)
+                        .ucmsb()
+                        .bit(reg.ucmsb)
+                        .uc7bit()
+                        .bit(reg.uc7bit)
+                        .ucspb()
+                        .bit(reg.ucspb)
+                        .ucssel()
+                        .bits(reg.ucssel)
+                        .ucrxeie()
+                        .bit(reg.ucrxeie)
+                        .ucbrkie()
+                        .bit(reg.ucbrkie)
+                });
+            }
+
+            #[inline(always)]
+            fn brw_wr(br: u16) {
+                unsafe { Self::steal() }
+                    .$brw()
+                    .write(|w| unsafe { w.bits(br) });
+            }
+
+            #[inline(always)]
+            fn mctlw_wr(os16: bool, brs: u8, brf: u8) {
+                unsafe { Self::steal() }
+                    .$mctlw
+                    .write(|w| unsafe { w.ucos16().bit(os16).ucbrs().bits(brs).ucbrf().bits(brf) });
+            }
+
+            #[inline(always)]
+            fn txifg_rd() -> bool {
+                unsafe { Self::steal() }.$ifg().read().uctxifg().bit()
+            }
+
+            #[inline(always)]
+            fn rxifg_rd() -> bool {
+                unsafe { Self::steal() }.$ifg().read().ucrxifg().bit()
+            }
+
+            #[inline(always)]
+            fn txbuf_wr(byte: u8) {
+                unsafe { Self::steal() }
+                    .$txbuf()
+                    .write(|w| unsafe { w.uctxbuf().bits(byte) });
+            }
+
+            #[inline(always)]
+            fn rxbuf_rd() -> u8 {
+                unsafe { Self::steal() }.$rxbuf().read().ucrxbuf().bits()
+            }
+
+            // Break also sets the framing error flag, so it's checked first
+            #[inline(always)]
+            fn rx_err_rd() -> Option<Error> {
+                let statw = unsafe { Self::steal() }.$statw().read();
+                if statw.ucbrk().bit_is_set() {
+                    Some(Error::Break)
+                } else if statw.ucfe().bit_is_set() {
+                    Some(Error::Framing)
+                } else if statw.ucpe().bit_is_set() {
+                    Some(Error::Parity)
+                } else if statw.ucoe().bit_is_set() {
+                    Some(Error::Overrun)
+                } else {
+                    None
+                }
+            }
+
+            #[inline(always)]
+            fn rx_err_clear() {
+                unsafe { Self::steal() }.$statw().modify(|_, w| {
+                    w.ucbrk()
+                        .clear_bit()
+                        .ucfe()
+                        .clear_bit()
+                        .ucpe()
+                        .clear_bit()
+                        .ucoe()
+                        .clear_bit()
+                        .ucrxerr()
+                        .clear_bit()
+                });
+            }
+
+            #[inline(always)]
+            fn overrun_rd() -> bool {
+                unsafe { Self::steal() }.$statw().read().ucoe().bit()
+            }
+
+            #[inline(always)]
+            fn busy_rd() -> bool {
+                unsafe { Self::steal() }.$statw().read().ucbusy().bit()
+            }
+
+            #[inline(always)]
+            fn rxie_wr(on: bool) {
+                unsafe { Self::steal() }
+                    .$ie()
+                    .modify(|_, w| w.ucrxie().bit(on));
+            }
+
+            #[inline(always)]
+            fn txie_wr(on: bool) {
+                unsafe { Self::steal() }
+                    .$ie()
+                    .modify(|_, w| w.uctxie().bit(on));
+            }
+
+            #[inline(always)]
+            fn txifg_set() {
+                unsafe { Self::steal() }
+                    .$ifg()
+                    .modify(|_, w| w.uctxifg().set_bit());
+            }
+
+            #[inline(always)]
+            fn iv_rd() -> u16 {
+                unsafe { Self::steal() }.$iv().read().bits()
+            }
+        }
+
+        impl SerialUsci for $USCI {}
+    };
+}
+
+serial_usci!(E_USCI_A0 => uca0ctlw0, uca0brw, uca0mctlw, uca0ifg, uca0txbuf, uca0rxbuf, uca0statw, uca0ie, uca0iv);
+serial_usci!(E_USCI_A1 => uca1ctlw0, uca1brw, uca1mctlw, uca1ifg, uca1txbuf, uca1rxbuf, uca1statw, uca1ie, uca1iv);
+
+// UCSSEL values
+const UCSSEL_UCLK: u8 = 0;
+const UCSSEL_ACLK: u8 = 1;
+const UCSSEL_SMCLK: u8 = 2;
+
 pub struct NoBaudConfig;
 
-pub enum BaudConfig {
-    Over16 { br: u16, brs: u8, brf: u8 },
-    Under16 { br: u16, brs: u8 },
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BaudConfig {
+    pub os16: bool,
+    pub br: u16,
+    pub brf: u8,
+    pub brs: u8,
+    clk_freq: u32,
+    bps: u32,
+}
+
+// Worst-case bit timing error over a frame, in hundredths of a percent of a bit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BaudTimingError {
+    pub tx: u16,
+    pub rx: u16,
 }
 
-pub struct SerialConfig<BAUD> {
-    periph: E_USCI_A1,
-    bit_order: UCMSB_A,
-    bit_cnt: UC7BIT_A,
-    stop_bits: UCSPB_A,
-    parity: Parity,
-    clk_sel: UCSSEL_A,
+// Once a baud rate is set, holds on to the clock it was worked out from, so the clocks can't be
+// reconfigured under the port
+pub struct SerialConfig<'c, USCI, BAUD> {
+    periph: USCI,
+    ctlw0: UcaCtlw0,
+    tolerance: Option<u16>,
     baud_config: BAUD,
+    _clk: PhantomData<&'c ()>,
 }
 
 macro_rules! mk_config {
     ($conf:expr, $baud:expr, $sel:expr) => {
         SerialConfig {
             periph: $conf.periph,
-            bit_order: $conf.bit_order,
-            bit_cnt: $conf.bit_cnt,
-            stop_bits: $conf.stop_bits,
-            parity: $conf.parity,
-            clk_sel: $sel,
+            ctlw0: UcaCtlw0 {
+                ucssel: $sel,
+                ..$conf.ctlw0
+            },
+            tolerance: $conf.tolerance,
             baud_config: $baud,
+            _clk: PhantomData,
         }
     };
 }
 
-pub trait UsciExt {
-    fn constrain(self) -> SerialConfig<NoBaudConfig>;
+pub trait UsciExt: Sized {
+    fn constrain(self) -> SerialConfig<'static, Self, NoBaudConfig>;
 }
 
-impl UsciExt for E_USCI_A1 {
-    fn constrain(self) -> SerialConfig<NoBaudConfig> {
+impl<USCI: SerialUsci> UsciExt for USCI {
+    fn constrain(self) -> SerialConfig<'static, Self, NoBaudConfig> {
         SerialConfig {
             periph: self,
-            bit_order: UCMSB_A::UCMSB_0,
-            bit_cnt: UC7BIT_A::_8BIT,
-            stop_bits: UCSPB_A::UCSPB_0,
-            parity: Parity::NoParity,
-            clk_sel: UCSSEL_A::ACLK,
+            ctlw0: UcaCtlw0 {
+                ucpen: false,
+                ucpar: false,
+                ucmsb: false,
+                uc7bit: false,
+                ucspb: false,
+                ucssel: UCSSEL_ACLK,
+                ucrxeie: false,
+                ucbrkie: false,
+            },
+            tolerance: None,
             baud_config: NoBaudConfig,
+            _clk: PhantomData,
         }
     }
 }
 
-impl<BAUD> SerialConfig<BAUD> {
+impl<USCI, BAUD> SerialConfig<'_, USCI, BAUD> {
     pub fn msb_first(mut self) -> Self {
-        self.bit_order = UCMSB_A::UCMSB_1;
+        self.ctlw0.ucmsb = true;
         self
     }
 
     pub fn lsb_first(mut self) -> Self {
-        self.bit_order = UCMSB_A::UCMSB_0;
+        self.ctlw0.ucmsb = false;
         self
     }
 
     pub fn char_7bits(mut self) -> Self {
-        self.bit_cnt = UC7BIT_A::_7BIT;
+        self.ctlw0.uc7bit = true;
         self
     }
 
     pub fn char_8bits(mut self) -> Self {
-        self.bit_cnt = UC7BIT_A::_8BIT;
+        self.ctlw0.uc7bit = false;
         self
     }
 
     pub fn stopbits_1(mut self) -> Self {
-        self.stop_bits = UCSPB_A::UCSPB_0;
+        self.ctlw0.ucspb = false;
         self
     }
 
     pub fn stopbits_2(mut self) -> Self {
-        self.stop_bits = UCSPB_A::UCSPB_1;
+        self.ctlw0.ucspb = true;
         self
     }
 
     pub fn parity_none(mut self) -> Self {
-        self.parity = Parity::NoParity;
+        self.ctlw0.ucpen = false;
         self
     }
 
     pub fn parity_even(mut self) -> Self {
-        self.parity = Parity::Even;
+        self.ctlw0.ucpen = true;
+        self.ctlw0.ucpar = true;
         self
     }
 
     pub fn parity_odd(mut self) -> Self {
-        self.parity = Parity::Odd;
+        self.ctlw0.ucpen = true;
+        self.ctlw0.ucpar = false;
+        self
+    }
+
+    // Characters received with errors and breaks still set UCRXIFG, so they can be fetched
+    // along with their error with Rx::read_char
+    pub fn keep_error_chars(mut self) -> Self {
+        self.ctlw0.ucrxeie = true;
+        self.ctlw0.ucbrkie = true;
+        self
+    }
+
+    pub fn discard_error_chars(mut self) -> Self {
+        self.ctlw0.ucrxeie = false;
+        self.ctlw0.ucbrkie = false;
+        self
+    }
+
+    // Makes freeze reject a baud rate whose TX or RX timing error over the final frame format
+    // exceeds max_err, in hundredths of a percent of a bit
+    pub fn baud_tolerance(mut self, max_err: u16) -> Self {
+        self.tolerance = Some(max_err);
         self
     }
 }
@@ -110,172 +361,924 @@ impl<BAUD> SerialConfig<BAUD> {
 pub enum BaudError {
     BpsTooHigh,
     BpsTooLow,
+    ToleranceExceeded(BaudTimingError),
 }
 
-impl SerialConfig<NoBaudConfig> {
+impl<'c, USCI> SerialConfig<'c, USCI, NoBaudConfig> {
     pub fn baudrate_aclk(
         self,
-        bps: u32,
+        bps: Bps,
         aclk: &Aclk,
-    ) -> Result<SerialConfig<BaudConfig>, BaudError> {
-        let baud_config = Self::calculate_baud_config(aclk.freq() as u32, bps)?;
-        Ok(mk_config!(self, baud_config, UCSSEL_A::ACLK))
+    ) -> Result<SerialConfig<'_, USCI, BaudConfig>, BaudError> {
+        self.baudrate(aclk.freq().0, bps.0, UCSSEL_ACLK)
     }
 
     pub fn baudrate_smclk(
         self,
-        bps: u32,
+        bps: Bps,
         smclk: &Smclk,
-    ) -> Result<SerialConfig<BaudConfig>, BaudError> {
-        let baud_config = Self::calculate_baud_config(smclk.freq(), bps)?;
-        Ok(mk_config!(self, baud_config, UCSSEL_A::SMCLK))
+    ) -> Result<SerialConfig<'_, USCI, BaudConfig>, BaudError> {
+        self.baudrate(smclk.freq().0, bps.0, UCSSEL_SMCLK)
     }
 
-    pub fn baudrate_external_uclk(
+    pub fn baudrate_external_uclk<F: TryInto<Hertz>>(
+        self,
+        bps: Bps,
+        clk_freq: F,
+    ) -> Result<SerialConfig<'c, USCI, BaudConfig>, BaudError> {
+        // Too fast to count in Hz is certainly too fast to divide down to a baud rate
+        let clk_freq = clk_freq.try_into().map_err(|_| BaudError::BpsTooLow)?;
+        self.baudrate(clk_freq.0, bps.0, UCSSEL_UCLK)
+    }
+
+    fn baudrate<'a>(
         self,
-        bps: u32,
         clk_freq: u32,
-    ) -> Result<SerialConfig<BaudConfig>, BaudError> {
-        let baud_config = Self::calculate_baud_config(clk_freq, bps)?;
-        Ok(mk_config!(self, baud_config, UCSSEL_A::UCLK))
+        bps: u32,
+        clk_sel: u8,
+    ) -> Result<SerialConfig<'a, USCI, BaudConfig>, BaudError> {
+        let baud_config = calculate_baud_config(clk_freq, bps)?;
+        Ok(mk_config!(self, baud_config, clk_sel))
     }
+}
+
+// Start bit, data, parity and stop bits
+fn frame_bits(ctlw0: &UcaCtlw0) -> u32 {
+    let data = if ctlw0.uc7bit { 7 } else { 8 };
+    let parity = if ctlw0.ucpen { 1 } else { 0 };
+    let stop = if ctlw0.ucspb { 2 } else { 1 };
+    1 + data + parity + stop
+}
 
-    fn calculate_baud_config(clk_freq: u32, bps: u32) -> Result<BaudConfig, BaudError> {
-        let n = clk_freq / bps;
-        if n == 0 {
-            Err(BaudError::BpsTooHigh)
-        } else if n > 0xFFFF {
-            Err(BaudError::BpsTooLow)
+// UG section 22.3.10: N = BRCLK / baud, with oversampling whenever N >= 16. UCBRSx comes from the
+// fractional part of N alone.
+fn calculate_baud_config(clk_freq: u32, bps: u32) -> Result<BaudConfig, BaudError> {
+    let n = clk_freq / bps;
+    if n == 0 {
+        Err(BaudError::BpsTooHigh)
+    } else if n > 0xFFFF {
+        Err(BaudError::BpsTooLow)
+    } else {
+        let brs = lookup_brs(clk_freq, bps);
+
+        if n >= 16 {
+            let div = bps * 16;
+            // n / 16, but more precise
+            let br = (clk_freq / div) as u16;
+            // same as n % 16, but more precise
+            let brf = ((clk_freq % div) / bps) as u8;
+            Ok(BaudConfig {
+                os16: true,
+                br,
+                brf,
+                brs,
+                clk_freq,
+                bps,
+            })
         } else {
-            let brs = Self::lookup_brs(clk_freq, bps);
-
-            if n >= 16 {
-                let div = bps * 16;
-                // n / 16, but more precise
-                let br = (clk_freq / div) as u16;
-                // same as n % 16, but more precise
-                let brf = ((clk_freq % div) / bps) as u8;
-                Ok(BaudConfig::Over16 { br, brf, brs })
-            } else {
-                Ok(BaudConfig::Under16 { br: n as u16, brs })
-            }
+            Ok(BaudConfig {
+                os16: false,
+                br: n as u16,
+                brf: 0,
+                brs,
+                clk_freq,
+                bps,
+            })
         }
     }
+}
+
+// UG table 22-4: smallest fractional part of N, in ten-thousandths, that each UCBRSx value is used
+// for
+const BRS_TABLE: [(u16, u8); 36] = [
+    (0, 0x00),
+    (529, 0x01),
+    (715, 0x02),
+    (835, 0x04),
+    (1001, 0x08),
+    (1252, 0x10),
+    (1430, 0x20),
+    (1670, 0x11),
+    (2147, 0x21),
+    (2224, 0x22),
+    (2503, 0x44),
+    (3000, 0x25),
+    (3335, 0x49),
+    (3575, 0x4A),
+    (3753, 0x52),
+    (4003, 0x92),
+    (4286, 0x53),
+    (4378, 0x55),
+    (5002, 0xAA),
+    (5715, 0x6B),
+    (6003, 0xAD),
+    (6254, 0xB5),
+    (6432, 0xB6),
+    (6667, 0xD6),
+    (7001, 0xB7),
+    (7147, 0xBB),
+    (7503, 0xDD),
+    (7861, 0xED),
+    (8004, 0xEE),
+    (8333, 0xBF),
+    (8464, 0xDF),
+    (8572, 0xEF),
+    (8751, 0xF7),
+    (9004, 0xFB),
+    (9170, 0xFD),
+    (9288, 0xFE),
+];
 
-    fn lookup_brs(clk_freq: u32, bps: u32) -> u8 {
-        let modulo = clk_freq % bps;
-
-        // Fractional part lookup for the baud rate. Not extremely precise
-        if modulo * 19 < bps {
-            0x0
-        } else if modulo * 14 < bps {
-            0x1
-        } else if modulo * 12 < bps {
-            0x2
-        } else if modulo * 10 < bps {
-            0x4
-        } else if modulo * 8 < bps {
-            0x8
-        } else if modulo * 7 < bps {
-            0x10
-        } else if modulo * 6 < bps {
-            0x20
-        } else if modulo * 5 < bps {
-            0x11
-        } else if modulo * 4 < bps {
-            0x22
-        } else if modulo * 3 < bps {
-            0x44
-        } else if modulo * 11 < bps * 4 {
-            0x49
-        } else if modulo * 5 < bps * 2 {
-            0x4A
-        } else if modulo * 7 < bps * 3 {
-            0x92
-        } else if modulo * 2 < bps {
-            0x53
-        } else if modulo * 7 < bps * 4 {
-            0xAA
-        } else if modulo * 13 < bps * 8 {
-            0x6B
-        } else if modulo * 3 < bps * 2 {
-            0xAD
-        } else if modulo * 11 < bps * 8 {
-            0xD6
-        } else if modulo * 4 < bps * 3 {
-            0xBB
-        } else if modulo * 5 < bps * 4 {
-            0xDD
-        } else if modulo * 9 < bps * 8 {
-            0xEF
+fn lookup_brs(clk_freq: u32, bps: u32) -> u8 {
+    // Truncated, since the table gives lower bounds
+    let frac = ((clk_freq % bps) as u64 * 10_000 / bps as u64) as u16;
+    BRS_TABLE
+        .iter()
+        .rev()
+        .find(|(lower, _)| *lower <= frac)
+        .map_or(0, |(_, brs)| *brs)
+}
+
+impl BaudConfig {
+    // BRCLK cycles taken by bit i of the frame. UCBRSx is applied MSB first, one bit of the
+    // pattern per frame bit, starting over every 8 bits.
+    fn bit_cycles(&self, i: u32) -> u32 {
+        let base = if self.os16 {
+            16 * self.br as u32 + self.brf as u32
         } else {
-            0xFD
+            self.br as u32
+        };
+        base + ((self.brs >> (7 - i % 8)) & 1) as u32
+    }
+
+    // UG section 22.3.11. The TX error of a bit is how far its end has drifted from the ideal. The
+    // RX error is the drift of its sample point in the middle of the bit, where the start edge can
+    // be picked up anywhere within one BRCLK cycle.
+    fn timing_error(&self, frame_bits: u32) -> BaudTimingError {
+        let clk = self.clk_freq as i64;
+        let bps = self.bps as i64;
+        // Times are in BRCLK cycles scaled by 2 * bps, which makes a bit 2 * clk long and keeps
+        // half bits whole
+        let mut elapsed = 0;
+        let mut tx = 0;
+        let mut rx = 0;
+        for i in 0..frame_bits as i64 {
+            let bit = self.bit_cycles(i as u32) as i64;
+            let sample = (2 * elapsed + bit) * bps - (2 * i + 1) * clk;
+            rx = max(rx, max(sample.abs(), (sample + 2 * bps).abs()));
+            elapsed += bit;
+            tx = max(tx, (2 * elapsed * bps - 2 * (i + 1) * clk).abs());
+        }
+        let to_err = |diff: i64| min(diff * 10_000 / (2 * clk), u16::MAX as i64) as u16;
+        BaudTimingError {
+            tx: to_err(tx),
+            rx: to_err(rx),
         }
     }
 }
 
-impl SerialConfig<BaudConfig> {
-    pub fn freeze(self) -> (Tx, Rx) {
-        self.periph.uca1ctlw0().write(|w| w.ucswrst().set_bit());
-        match self.baud_config {
-            BaudConfig::Over16 { brs, brf, br } => {
-                self.periph.uca1brw().write(|w| unsafe { w.bits(br) });
-                self.periph
-                    .uca1mctlw
-                    .write(|w| unsafe { w.ucos16().set_bit().ucbrs().bits(brs).ucbrf().bits(brf) });
-            }
-            BaudConfig::Under16 { br, brs } => {
-                self.periph.uca1brw().write(|w| unsafe { w.bits(br) });
-                self.periph
-                    .uca1mctlw
-                    .write(|w| unsafe { w.ucos16().clear_bit().ucbrs().bits(brs) });
+impl<USCI> SerialConfig<'_, USCI, BaudConfig> {
+    // Worst-case timing error with the current frame format
+    pub fn baud_error(&self) -> BaudTimingError {
+        self.baud_config.timing_error(frame_bits(&self.ctlw0))
+    }
+}
+
+impl<'c, USCI: SerialUsci> SerialConfig<'c, USCI, BaudConfig> {
+    pub fn freeze<TX: TxPin<USCI>, RX: RxPin<USCI>>(
+        self,
+        _tx: TX,
+        _rx: RX,
+    ) -> Result<(Tx<'c, USCI>, Rx<'c, USCI>), BaudError> {
+        if let Some(max_err) = self.tolerance {
+            let err = self.baud_error();
+            if err.tx > max_err || err.rx > max_err {
+                return Err(BaudError::ToleranceExceeded(err));
             }
         }
 
-        self.periph.uca1ctlw0().write(|w| {
-            w.ucmsb()
-                .variant(self.bit_order)
-                .uc7bit()
-                .variant(self.bit_cnt)
-                .ucspb()
-                .variant(self.stop_bits)
-                .ucssel()
-                .variant(self.clk_sel);
-
-            match self.parity {
-                Parity::Odd => w.ucpen().set_bit().ucpar().odd(),
-                Parity::Even => w.ucpen().set_bit().ucpar().even(),
-                Parity::NoParity => w.ucpen().clear_bit(),
-            }
-        });
-        (Tx, Rx)
+        USCI::ctlw0_reset();
+        let baud = self.baud_config;
+        USCI::brw_wr(baud.br);
+        USCI::mctlw_wr(baud.os16, baud.brs, baud.brf);
+        USCI::ctlw0_wr(&self.ctlw0);
+        Ok((Tx(PhantomData), Rx(PhantomData)))
     }
 }
 
-pub struct Tx;
-pub struct Rx;
+pub struct Tx<'c, USCI>(PhantomData<(USCI, &'c ())>);
+pub struct Rx<'c, USCI>(PhantomData<(USCI, &'c ())>);
+
+impl<USCI: SerialUsci> serial::Write<u8> for Tx<'_, USCI> {
+    type Error = Infallible;
 
-impl Tx {
-    pub fn write(&mut self, byte: u8) -> Result<(), ()> {
-        let uart = unsafe { &*E_USCI_A1::ptr() };
-        if uart.uca1ifg().read().uctxifg().is_uctxifg_0() {
-            Err(())
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        if USCI::txifg_rd() {
+            USCI::txbuf_wr(byte);
+            Ok(())
         } else {
-            uart.uca1txbuf()
-                .write(|w| unsafe { w.uctxbuf().bits(byte) });
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    // Done once TXBUF is empty and the last character has left the shift register. UCBUSY is also
+    // set while a character is being received, so this waits out RX traffic as well.
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        if USCI::txifg_rd() && !USCI::busy_rd() {
             Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
         }
     }
 }
 
-impl Rx {
-    pub fn read(&self) -> Result<u8, ()> {
-        let uart = unsafe { &*E_USCI_A1::ptr() };
-        if uart.uca1ifg().read().ucrxifg().is_ucrxifg_0() {
-            Err(())
+// Provides bwrite_all and bflush
+impl<USCI: SerialUsci> serial_write::Default<u8> for Tx<'_, USCI> {}
+
+impl<USCI: SerialUsci> fmt::Write for Tx<'_, USCI> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.bwrite_all(s.as_bytes()) {
+            Ok(()) => Ok(()),
+            Err(never) => match never {},
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    Framing,
+    Parity,
+    Overrun,
+    Break,
+}
+
+impl<USCI: SerialUsci> Rx<'_, USCI> {
+    // Received character along with any error flagged for it. The status flags are cleared by
+    // reading RXBUF, so they have to be read first.
+    pub fn read_char(&mut self) -> Option<(u8, Option<Error>)> {
+        if USCI::rxifg_rd() {
+            let err = USCI::rx_err_rd();
+            Some((USCI::rxbuf_rd(), err))
         } else {
-            let bits = uart.uca1rxbuf().read().ucrxbuf().bits();
-            Ok(bits)
+            None
+        }
+    }
+}
+
+impl<USCI: SerialUsci> serial::Read<u8> for Rx<'_, USCI> {
+    type Error = Error;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        match self.read_char() {
+            Some((byte, None)) => Ok(byte),
+            Some((_, Some(err))) => Err(nb::Error::Other(err)),
+            // Discarded characters leave their flags behind without setting UCRXIFG. Clearing them
+            // through STATW rather than a dummy RXBUF read can't eat a character that just arrived.
+            None => match USCI::rx_err_rd() {
+                Some(err) => {
+                    USCI::rx_err_clear();
+                    Err(nb::Error::Other(err))
+                }
+                None => Err(nb::Error::WouldBlock),
+            },
+        }
+    }
+}
+
+// Single-producer single-consumer byte queue shared by the UART vector and the main code. Each
+// side only writes its own index and 16-bit stores can't tear, so no critical section is needed.
+// Indices run modulo 2N so that a full queue can be told apart from an empty one.
+struct RingBuf<const N: usize> {
+    buf: UnsafeCell<[u8; N]>,
+    head: UnsafeCell<usize>,
+    tail: UnsafeCell<usize>,
+}
+
+impl<const N: usize> RingBuf<N> {
+    // The index maths divides by N, so an empty queue is rejected at build time
+    const NONEMPTY: () = assert!(N > 0, "serial buffers need room for at least one byte");
+
+    const fn new() -> Self {
+        let () = Self::NONEMPTY;
+        RingBuf {
+            buf: UnsafeCell::new([0; N]),
+            head: UnsafeCell::new(0),
+            tail: UnsafeCell::new(0),
+        }
+    }
+
+    fn slot(&self, idx: usize) -> *mut u8 {
+        unsafe { (self.buf.get() as *mut u8).add(idx % N) }
+    }
+
+    fn len(&self) -> usize {
+        let head = unsafe { self.head.get().read_volatile() };
+        let tail = unsafe { self.tail.get().read_volatile() };
+        (tail + 2 * N - head) % (2 * N)
+    }
+
+    // Producer side only
+    fn push(&self, byte: u8) -> bool {
+        if self.len() == N {
+            return false;
+        }
+        let tail = unsafe { self.tail.get().read_volatile() };
+        // Only the slot being filled is touched, since the consumer can be reading the others
+        unsafe { self.slot(tail).write_volatile(byte) };
+        // The byte has to land before the consumer can see it
+        compiler_fence(Ordering::SeqCst);
+        unsafe { self.tail.get().write_volatile((tail + 1) % (2 * N)) };
+        true
+    }
+
+    // Consumer side only
+    fn pop(&self) -> Option<u8> {
+        if self.len() == 0 {
+            return None;
         }
+        let head = unsafe { self.head.get().read_volatile() };
+        let byte = unsafe { self.slot(head).read_volatile() };
+        compiler_fence(Ordering::SeqCst);
+        unsafe { self.head.get().write_volatile((head + 1) % (2 * N)) };
+        Some(byte)
+    }
+}
+
+// UCAxIV values
+const IV_RXIFG: u16 = 0x2;
+const IV_TXIFG: u16 = 0x4;
+
+// Event count that only the vector increments. A single word, so reading it is never torn.
+struct Counter(UnsafeCell<u16>);
+
+impl Counter {
+    const fn new() -> Self {
+        Counter(UnsafeCell::new(0))
+    }
+
+    fn get(&self) -> u16 {
+        unsafe { self.0.get().read_volatile() }
+    }
+
+    fn bump(&self) {
+        let count = self.0.get();
+        unsafe { count.write_volatile(count.read_volatile().wrapping_add(1)) };
+    }
+}
+
+struct RxCounters {
+    dropped: Counter,
+    overruns: Counter,
+    framing: Counter,
+    parity: Counter,
+    breaks: Counter,
+}
+
+impl RxCounters {
+    const fn new() -> Self {
+        RxCounters {
+            dropped: Counter::new(),
+            overruns: Counter::new(),
+            framing: Counter::new(),
+            parity: Counter::new(),
+            breaks: Counter::new(),
+        }
+    }
+
+    fn snapshot(&self) -> RxErrors {
+        RxErrors {
+            dropped: self.dropped.get(),
+            overruns: self.overruns.get(),
+            framing: self.framing.get(),
+            parity: self.parity.get(),
+            breaks: self.breaks.get(),
+        }
+    }
+}
+
+// Buffers for interrupt-driven serial. Declare one as a static for the port, attach it to the
+// port's Tx and Rx, and call handle_intr from the EUSCI_Ax vector. RX bytes that arrive while the
+// RX queue is full are dropped, and along with receive errors are reported by the next read_buf.
+pub struct SerialBuffers<USCI, const RXN: usize, const TXN: usize> {
+    rx: RingBuf<RXN>,
+    tx: RingBuf<TXN>,
+    rx_errors: RxCounters,
+    _usci: PhantomData<USCI>,
+}
+
+// The queues are only ever touched by one producer and one consumer each
+unsafe impl<USCI, const RXN: usize, const TXN: usize> Sync for SerialBuffers<USCI, RXN, TXN> {}
+
+impl<USCI, const RXN: usize, const TXN: usize> SerialBuffers<USCI, RXN, TXN> {
+    pub const fn new() -> Self {
+        SerialBuffers {
+            rx: RingBuf::new(),
+            tx: RingBuf::new(),
+            rx_errors: RxCounters::new(),
+            _usci: PhantomData,
+        }
+    }
+}
+
+impl<USCI, const RXN: usize, const TXN: usize> Default for SerialBuffers<USCI, RXN, TXN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<USCI: SerialUsci, const RXN: usize, const TXN: usize> SerialBuffers<USCI, RXN, TXN> {
+    pub fn attach<'c>(
+        &'static self,
+        tx: Tx<'c, USCI>,
+        rx: Rx<'c, USCI>,
+    ) -> BufferedSerial<'c, USCI, RXN, TXN> {
+        let rx_seen = self.rx_errors.snapshot();
+        USCI::rxie_wr(true);
+        BufferedSerial {
+            bufs: self,
+            tx,
+            rx,
+            rx_seen,
+        }
+    }
+
+    /// # Safety
+    /// Must only be called from the port's EUSCI_Ax vector, since it's the sole producer of the
+    /// RX queue and sole consumer of the TX queue
+    pub unsafe fn handle_intr(&self) {
+        loop {
+            match USCI::iv_rd() {
+                0 => break,
+                iv => self.handle_iv(iv),
+            }
+        }
+    }
+
+    // Reading UCAxIV already cleared the flag it reported
+    fn handle_iv(&self, iv: u16) {
+        match iv {
+            // The error flags describe the byte in RXBUF, and reading it clears them. Bytes with
+            // framing or parity errors only get this far if they're being kept, so they're queued
+            // like any other.
+            IV_RXIFG => {
+                let counters = &self.rx_errors;
+                match USCI::rx_err_rd() {
+                    Some(Error::Break) => counters.breaks.bump(),
+                    Some(Error::Framing) => counters.framing.bump(),
+                    Some(Error::Parity) => counters.parity.bump(),
+                    _ => {}
+                }
+                if USCI::overrun_rd() {
+                    counters.overruns.bump();
+                }
+                if !self.rx.push(USCI::rxbuf_rd()) {
+                    counters.dropped.bump();
+                }
+            }
+            IV_TXIFG => match self.tx.pop() {
+                Some(byte) => USCI::txbuf_wr(byte),
+                // TXBUF is empty, so put the flag back for the next write_buf to trigger off
+                None => {
+                    USCI::txie_wr(false);
+                    USCI::txifg_set();
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+// Receive problems counted by the vector, by class
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RxErrors {
+    // Bytes lost because the RX queue was full
+    pub dropped: u16,
+    // Bytes lost because RXBUF was overwritten before the vector got to it
+    pub overruns: u16,
+    pub framing: u16,
+    pub parity: u16,
+    pub breaks: u16,
+}
+
+impl RxErrors {
+    // Counts since an earlier snapshot
+    fn since(&self, prev: &RxErrors) -> RxErrors {
+        RxErrors {
+            dropped: self.dropped.wrapping_sub(prev.dropped),
+            overruns: self.overruns.wrapping_sub(prev.overruns),
+            framing: self.framing.wrapping_sub(prev.framing),
+            parity: self.parity.wrapping_sub(prev.parity),
+            breaks: self.breaks.wrapping_sub(prev.breaks),
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        *self != RxErrors::default()
+    }
+}
+
+pub struct BufferedSerial<'c, USCI: 'static, const RXN: usize, const TXN: usize> {
+    bufs: &'static SerialBuffers<USCI, RXN, TXN>,
+    tx: Tx<'c, USCI>,
+    rx: Rx<'c, USCI>,
+    // Error counts as of the last report
+    rx_seen: RxErrors,
+}
+
+impl<'c, USCI: SerialUsci, const RXN: usize, const TXN: usize> BufferedSerial<'c, USCI, RXN, TXN> {
+    // Copies out as many received bytes as fit. If anything went wrong since the last call, only
+    // the errors are reported, and the bytes that did make it in are left for the next call.
+    pub fn read_buf(&mut self, buf: &mut [u8]) -> Result<usize, RxErrors> {
+        let now = self.bufs.rx_errors.snapshot();
+        let errors = now.since(&self.rx_seen);
+        if errors.any() {
+            self.rx_seen = now;
+            return Err(errors);
+        }
+
+        let mut n = 0;
+        for slot in buf.iter_mut() {
+            match self.bufs.rx.pop() {
+                Some(byte) => *slot = byte,
+                None => break,
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    // Queues as many bytes as fit and returns how many were taken
+    pub fn write_buf(&mut self, data: &[u8]) -> usize {
+        let n = data
+            .iter()
+            .take_while(|&&byte| self.bufs.tx.push(byte))
+            .count();
+        if n > 0 {
+            USCI::txie_wr(true);
+        }
+        n
+    }
+
+    // Bytes still waiting to be sent
+    pub fn tx_pending(&self) -> usize {
+        self.bufs.tx.len()
+    }
+
+    pub fn release(self) -> (Tx<'c, USCI>, Rx<'c, USCI>) {
+        USCI::rxie_wr(false);
+        USCI::txie_wr(false);
+        (self.tx, self.rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::{GpioExt, PmmExt};
+    use crate::regs::mock;
+    use crate::time::U32Ext;
+    use core::fmt::Write as _;
+    use embedded_hal::serial::{Read, Write};
+
+    fn uart() -> &'static pac::e_usci_a1::RegisterBlock {
+        unsafe { E_USCI_A1::steal() }
+    }
+
+    fn freeze_9600() -> (Tx<'static, E_USCI_A1>, Rx<'static, E_USCI_A1>) {
+        let periph = unsafe { pac::Peripherals::steal() };
+        let pmm = periph.PMM.freeze();
+        let p4 = periph.P4.constrain().unlock(&pmm).split();
+        let tx = p4.p4_3.alternate1(&p4.psel);
+        let rx = p4.p4_2.alternate1(&p4.psel);
+        periph
+            .E_USCI_A1
+            .constrain()
+            .parity_even()
+            .baudrate_external_uclk(9600.bps(), 1.mhz())
+            .unwrap()
+            .freeze(tx, rx)
+            .unwrap()
+    }
+
+    #[test]
+    fn freeze() {
+        mock::reset();
+        freeze_9600();
+
+        assert_eq!(uart().uca1brw().read().bits(), 6);
+        let mctlw = uart().uca1mctlw.read();
+        assert!(mctlw.ucos16().bit_is_set());
+        assert_eq!(mctlw.ucbrf().bits(), 8);
+        assert_eq!(mctlw.ucbrs().bits(), 0x20);
+        let ctlw0 = uart().uca1ctlw0().read();
+        assert!(ctlw0.ucswrst().bit_is_clear());
+        assert!(ctlw0.ucpen().bit_is_set());
+        assert!(ctlw0.ucpar().is_even());
+        assert!(ctlw0.ucssel().is_uclk());
+    }
+
+    #[test]
+    fn tx_write() {
+        mock::reset();
+        let (mut tx, _rx) = freeze_9600();
+
+        assert!(matches!(tx.write(b'a'), Err(nb::Error::WouldBlock)));
+        assert!(matches!(tx.flush(), Err(nb::Error::WouldBlock)));
+        uart().uca1ifg().write(|w| w.uctxifg().set_bit());
+        assert!(tx.write(b'a').is_ok());
+        assert!(tx.flush().is_ok());
+        assert_eq!(uart().uca1txbuf().read().bits(), b'a' as u16);
+
+        // TXBUF is free but the shift register is still sending
+        let statw = uart().uca1statw() as *const _ as *mut u16;
+        unsafe { statw.write(1) };
+        assert!(matches!(tx.flush(), Err(nb::Error::WouldBlock)));
+        unsafe { statw.write(0) };
+        assert!(tx.flush().is_ok());
+    }
+
+    #[test]
+    fn fmt_write() {
+        mock::reset();
+        let (mut tx, mut rx) = freeze_9600();
+
+        assert!(matches!(rx.read(), Err(nb::Error::WouldBlock)));
+        uart().uca1ifg().write(|w| w.uctxifg().set_bit());
+        write!(tx, "{:x}", 0xbeefu16).unwrap();
+        // Only the last byte is left in TXBUF
+        assert_eq!(uart().uca1txbuf().read().bits(), b'f' as u16);
+    }
+
+    #[test]
+    fn rx_errors() {
+        mock::reset();
+        let (_tx, mut rx) = freeze_9600();
+        let statw = uart().uca1statw();
+
+        // Parity error on a character that was let through
+        uart().uca1ifg().write(|w| w.ucrxifg().set_bit());
+        statw.write(|w| w.ucpe().set_bit().ucrxerr().set_bit());
+        assert!(matches!(rx.read(), Err(nb::Error::Other(Error::Parity))));
+        assert_eq!(rx.read_char(), Some((0, Some(Error::Parity))));
+
+        // Break wins over the framing error it comes with
+        statw.write(|w| w.ucbrk().set_bit().ucfe().set_bit());
+        assert_eq!(rx.read_char(), Some((0, Some(Error::Break))));
+
+        // Overrun reported even though no character is pending
+        uart().uca1ifg().reset();
+        statw.write(|w| w.ucoe().set_bit());
+        assert!(matches!(rx.read(), Err(nb::Error::Other(Error::Overrun))));
+        assert!(statw.read().ucoe().bit_is_clear());
+        assert!(matches!(rx.read(), Err(nb::Error::WouldBlock)));
+        assert_eq!(rx.read_char(), None);
+    }
+
+    #[test]
+    fn keep_error_chars() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let pmm = periph.PMM.freeze();
+        let p4 = periph.P4.constrain().unlock(&pmm).split();
+        let tx = p4.p4_3.alternate1(&p4.psel);
+        let rx = p4.p4_2.alternate1(&p4.psel);
+        periph
+            .E_USCI_A1
+            .constrain()
+            .keep_error_chars()
+            .baudrate_external_uclk(9600.bps(), 1.mhz())
+            .unwrap()
+            .freeze(tx, rx)
+            .unwrap();
+
+        let ctlw0 = uart().uca1ctlw0().read();
+        assert!(ctlw0.ucrxeie().bit_is_set());
+        assert!(ctlw0.ucbrkie().bit_is_set());
+    }
+
+    #[test]
+    fn two_ports() {
+        mock::reset();
+        let (mut tx1, _rx1) = freeze_9600();
+
+        let periph = unsafe { pac::Peripherals::steal() };
+        let pmm = periph.PMM.freeze();
+        let p1 = periph.P1.constrain().unlock(&pmm).split();
+        let tx = p1.p1_7.alternate1(&p1.psel);
+        let rx = p1.p1_6.alternate1(&p1.psel);
+        let (mut tx0, mut rx0) = periph
+            .E_USCI_A0
+            .constrain()
+            .msb_first()
+            .stopbits_2()
+            .baudrate_external_uclk(115200.bps(), 1.mhz())
+            .unwrap()
+            .freeze(tx, rx)
+            .unwrap();
+
+        let uart0 = unsafe { E_USCI_A0::steal() };
+        assert_eq!(uart0.uca0brw().read().bits(), 8);
+        assert!(uart0.uca0mctlw.read().ucos16().bit_is_clear());
+        let ctlw0 = uart0.uca0ctlw0().read();
+        assert!(ctlw0.ucmsb().bit_is_set());
+        assert!(ctlw0.ucspb().bit_is_set());
+        assert!(ctlw0.ucpen().bit_is_clear());
+        // A1 keeps its own settings
+        assert_eq!(uart().uca1brw().read().bits(), 6);
+        assert!(uart().uca1ctlw0().read().ucmsb().bit_is_clear());
+
+        uart0
+            .uca0ifg()
+            .write(|w| w.uctxifg().set_bit().ucrxifg().set_bit());
+        // RXBUF is read-only to the PAC, so poke the mock memory directly
+        unsafe { (uart0.uca0rxbuf() as *const _ as *mut u16).write(b'z' as u16) };
+        assert!(tx0.write(b'b').is_ok());
+        assert_eq!(rx0.read().ok(), Some(b'z'));
+        assert_eq!(uart0.uca0txbuf().read().bits(), b'b' as u16);
+        assert!(tx1.write(b'c').is_err());
+    }
+
+    #[test]
+    fn ring_buf() {
+        mock::reset();
+        let ring = RingBuf::<3>::new();
+        assert_eq!(ring.pop(), None);
+        // Go around a few times to cover the index wrapping
+        for i in 0..4 {
+            assert!(ring.push(i));
+            assert!(ring.push(i + 1));
+            assert!(ring.push(i + 2));
+            assert!(!ring.push(i + 3));
+            assert_eq!(ring.len(), 3);
+            assert_eq!(ring.pop(), Some(i));
+            assert_eq!(ring.pop(), Some(i + 1));
+            assert_eq!(ring.pop(), Some(i + 2));
+            assert_eq!(ring.pop(), None);
+        }
+    }
+
+    #[test]
+    fn buffered() {
+        mock::reset();
+        static BUFS: SerialBuffers<E_USCI_A1, 4, 4> = SerialBuffers::new();
+        let (tx, rx) = freeze_9600();
+        let mut serial = BUFS.attach(tx, rx);
+        assert!(uart().uca1ie().read().ucrxie().bit_is_set());
+
+        let rxbuf = uart().uca1rxbuf() as *const _ as *mut u16;
+        for byte in b"abcde" {
+            unsafe { rxbuf.write(*byte as u16) };
+            BUFS.handle_iv(IV_RXIFG);
+        }
+        let mut buf = [0; 8];
+        assert_eq!(
+            serial.read_buf(&mut buf),
+            Err(RxErrors {
+                dropped: 1,
+                ..RxErrors::default()
+            })
+        );
+        assert_eq!(serial.read_buf(&mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"abcd");
+        assert_eq!(serial.read_buf(&mut buf), Ok(0));
+
+        // Each error class gets its own count, and the flagged bytes are still queued
+        let statw = uart().uca1statw();
+        unsafe { rxbuf.write(b'p' as u16) };
+        statw.write(|w| w.ucpe().set_bit().ucrxerr().set_bit());
+        BUFS.handle_iv(IV_RXIFG);
+        unsafe { rxbuf.write(b'f' as u16) };
+        statw.write(|w| w.ucfe().set_bit().ucoe().set_bit().ucrxerr().set_bit());
+        BUFS.handle_iv(IV_RXIFG);
+        unsafe { rxbuf.write(0) };
+        statw.write(|w| w.ucbrk().set_bit().ucfe().set_bit().ucrxerr().set_bit());
+        BUFS.handle_iv(IV_RXIFG);
+        statw.reset();
+        assert_eq!(
+            serial.read_buf(&mut buf),
+            Err(RxErrors {
+                dropped: 0,
+                overruns: 1,
+                framing: 1,
+                parity: 1,
+                breaks: 1,
+            })
+        );
+        assert_eq!(serial.read_buf(&mut buf), Ok(3));
+        assert_eq!(&buf[..3], b"pf\0");
+
+        assert_eq!(serial.write_buf(b"hello"), 4);
+        assert_eq!(serial.tx_pending(), 4);
+        assert!(uart().uca1ie().read().uctxie().bit_is_set());
+        for byte in b"hell" {
+            BUFS.handle_iv(IV_TXIFG);
+            assert_eq!(uart().uca1txbuf().read().bits(), *byte as u16);
+        }
+        // Queue drained, so the interrupt goes quiet with the flag left set
+        uart().uca1ifg().reset();
+        BUFS.handle_iv(IV_TXIFG);
+        assert!(uart().uca1ie().read().uctxie().bit_is_clear());
+        assert!(uart().uca1ifg().read().uctxifg().bit_is_set());
+
+        let (_tx, _rx) = serial.release();
+        assert!(uart().uca1ie().read().ucrxie().bit_is_clear());
+    }
+
+    // Recommended settings from UG table 22-5. A few entries there were tuned by hand past what
+    // table 22-4 gives (8 MHz at 9600 uses 0x49), so only ones that follow the table are checked.
+    #[test]
+    fn baud_ti_table() {
+        mock::reset();
+        let table = [
+            (32768, 1200, true, 1, 11, 0x25),
+            (32768, 2400, false, 13, 0, 0xB6),
+            (32768, 4800, false, 6, 0, 0xEE),
+            (32768, 9600, false, 3, 0, 0x92),
+            (1_000_000, 9600, true, 6, 8, 0x20),
+            (1_000_000, 19200, true, 3, 4, 0x02),
+            (1_000_000, 38400, true, 1, 10, 0x00),
+            (1_000_000, 57600, true, 1, 1, 0x4A),
+            (1_000_000, 115200, false, 8, 0, 0xD6),
+            (1_048_576, 9600, true, 6, 13, 0x22),
+            (8_000_000, 115200, true, 4, 5, 0x55),
+            (16_000_000, 115200, true, 8, 10, 0xF7),
+        ];
+        for &(clk, bps, os16, br, brf, brs) in table.iter() {
+            let conf = calculate_baud_config(clk, bps).unwrap();
+            assert_eq!(
+                (conf.os16, conf.br, conf.brf, conf.brs),
+                (os16, br, brf, brs),
+                "{} Hz at {} bps",
+                clk,
+                bps
+            );
+        }
+    }
+
+    #[test]
+    fn baud_error() {
+        mock::reset();
+        // Table 22-5 gives -7.36% TX and -17.04% RX as the worst case here
+        let conf = calculate_baud_config(1_000_000, 115200).unwrap();
+        assert_eq!(conf.timing_error(10), BaudTimingError { tx: 736, rx: 1704 });
+
+        let err = calculate_baud_config(8_000_000, 9600)
+            .unwrap()
+            .timing_error(10);
+        assert!(err.tx < 10 && err.rx < 20);
+        assert_eq!(lookup_brs(1_000_000, 9600), 0x20);
+        // Right on a table boundary
+        assert_eq!(lookup_brs(10_000 + 5002, 10_000), 0xAA);
+        assert_eq!(lookup_brs(10_000 + 5001, 10_000), 0x55);
+    }
+
+    #[test]
+    fn baud_tolerance() {
+        mock::reset();
+        let freeze = |conf: SerialConfig<'static, E_USCI_A1, BaudConfig>| {
+            let periph = unsafe { pac::Peripherals::steal() };
+            let pmm = periph.PMM.freeze();
+            let p4 = periph.P4.constrain().unlock(&pmm).split();
+            conf.freeze(p4.p4_3.alternate1(&p4.psel), p4.p4_2.alternate1(&p4.psel))
+        };
+        let config = |clk: u32| {
+            unsafe { pac::Peripherals::steal() }
+                .E_USCI_A1
+                .constrain()
+                .baudrate_external_uclk(115200.bps(), Hertz(clk))
+                .unwrap()
+        };
+
+        let conf = config(1_000_000);
+        assert_eq!(conf.baud_error().rx, 1704);
+        assert!(freeze(conf).is_ok());
+
+        assert!(matches!(
+            freeze(config(1_000_000).baud_tolerance(500)),
+            Err(BaudError::ToleranceExceeded(BaudTimingError {
+                tx: 736,
+                ..
+            }))
+        ));
+        assert!(freeze(config(8_000_000).baud_tolerance(500)).is_ok());
+
+        // The longer frame only gets set after the baud rate, and still counts
+        let conf = config(8_000_000).baud_tolerance(190);
+        assert_eq!(conf.baud_error().rx, 184);
+        let conf = conf.parity_even().stopbits_2();
+        assert!(matches!(
+            freeze(conf),
+            Err(BaudError::ToleranceExceeded(BaudTimingError {
+                rx: 200,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn baud_range() {
+        mock::reset();
+        assert!(calculate_baud_config(9600, 115200).is_err());
+        assert!(calculate_baud_config(16_000_000, 200).is_err());
     }
 }
@@ -0,0 +1,157 @@
+use core::convert::{TryFrom, TryInto};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Hertz(pub u32);
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KiloHertz(pub u32);
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MegaHertz(pub u32);
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Bps(pub u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Microseconds(pub u32);
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Milliseconds(pub u32);
+
+pub trait U32Ext {
+    fn hz(self) -> Hertz;
+    fn khz(self) -> KiloHertz;
+    fn mhz(self) -> MegaHertz;
+    fn bps(self) -> Bps;
+    fn us(self) -> Microseconds;
+    fn ms(self) -> Milliseconds;
+}
+
+impl U32Ext for u32 {
+    fn hz(self) -> Hertz {
+        Hertz(self)
+    }
+
+    fn khz(self) -> KiloHertz {
+        KiloHertz(self)
+    }
+
+    fn mhz(self) -> MegaHertz {
+        MegaHertz(self)
+    }
+
+    fn bps(self) -> Bps {
+        Bps(self)
+    }
+
+    fn us(self) -> Microseconds {
+        Microseconds(self)
+    }
+
+    fn ms(self) -> Milliseconds {
+        Milliseconds(self)
+    }
+}
+
+// The converted value doesn't fit in the u32 of the target unit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Overflow;
+
+// Same as the TryFrom impls, but usable in const items
+impl KiloHertz {
+    pub const fn to_hz(self) -> Option<Hertz> {
+        match self.0.checked_mul(1_000) {
+            Some(hz) => Some(Hertz(hz)),
+            None => None,
+        }
+    }
+}
+
+impl MegaHertz {
+    pub const fn to_hz(self) -> Option<Hertz> {
+        match self.0.checked_mul(1_000_000) {
+            Some(hz) => Some(Hertz(hz)),
+            None => None,
+        }
+    }
+}
+
+impl TryFrom<KiloHertz> for Hertz {
+    type Error = Overflow;
+
+    fn try_from(khz: KiloHertz) -> Result<Self, Overflow> {
+        khz.to_hz().ok_or(Overflow)
+    }
+}
+
+impl TryFrom<MegaHertz> for Hertz {
+    type Error = Overflow;
+
+    fn try_from(mhz: MegaHertz) -> Result<Self, Overflow> {
+        mhz.to_hz().ok_or(Overflow)
+    }
+}
+
+impl TryFrom<MegaHertz> for KiloHertz {
+    type Error = Overflow;
+
+    fn try_from(mhz: MegaHertz) -> Result<Self, Overflow> {
+        mhz.0.checked_mul(1_000).map(KiloHertz).ok_or(Overflow)
+    }
+}
+
+impl TryFrom<Milliseconds> for Microseconds {
+    type Error = Overflow;
+
+    fn try_from(ms: Milliseconds) -> Result<Self, Overflow> {
+        ms.0.checked_mul(1_000).map(Microseconds).ok_or(Overflow)
+    }
+}
+
+impl Hertz {
+    // Number of clock cycles in the duration, rounded to the nearest cycle. None if it doesn't
+    // fit in a u32.
+    pub fn ticks<D: TryInto<Microseconds>>(self, dur: D) -> Option<u32> {
+        self.ticks_divided(dur.try_into().ok()?, 1)
+    }
+
+    // Same as ticks, but for a clock divided by div. Dividing the product in one go means nothing
+    // is lost when div doesn't divide the frequency.
+    pub(crate) fn ticks_divided(self, dur: Microseconds, div: u32) -> Option<u32> {
+        let denom = 1_000_000 * div as u64;
+        let ticks = (self.0 as u64 * dur.0 as u64 + denom / 2) / denom;
+        if ticks > u32::MAX as u64 {
+            None
+        } else {
+            Some(ticks as u32)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Hertz::try_from(8.mhz()), Ok(8_000_000.hz()));
+        assert_eq!(Hertz::try_from(32.khz()), Ok(Hertz(32_000)));
+        assert_eq!(KiloHertz::try_from(24.mhz()), Ok(24_000.khz()));
+        assert_eq!(Microseconds::try_from(15.ms()), Ok(15_000.us()));
+
+        assert_eq!(Hertz::try_from(4_295.mhz()), Err(Overflow));
+        assert_eq!(Hertz::try_from(4_294_968.khz()), Err(Overflow));
+        assert_eq!(KiloHertz::try_from(4_294_968.mhz()), Err(Overflow));
+        assert_eq!(Microseconds::try_from(4_294_968.ms()), Err(Overflow));
+        assert_eq!(MegaHertz(4_294).to_hz(), Some(Hertz(4_294_000_000)));
+    }
+
+    #[test]
+    fn ticks() {
+        assert_eq!(Hertz(1_000_000).ticks(1.ms()), Some(1_000));
+        assert_eq!(Hertz(32768).ticks(1.ms()), Some(33));
+        assert_eq!(Hertz(32768).ticks(1_000_000.ms()), Some(32_768_000));
+        assert_eq!(Hertz(24_000_000).ticks(1_000_000.ms()), None);
+        // Too long to even be counted in microseconds
+        assert_eq!(Hertz(1).ticks(u32::MAX.ms()), None);
+        // 32768 Hz / 3 over 3 ms is 32.768 ticks, which per-step rounding would get wrong
+        assert_eq!(Hertz(32768).ticks_divided(3_000.us(), 3), Some(33));
+        assert_eq!(Hertz(10_000).ticks_divided(100_000.us(), 48), Some(21));
+    }
+}
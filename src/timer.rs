@@ -1,33 +1,66 @@
-use crate::clocks::{Aclk, Smclk};
+use crate::clocks::{Aclk, Clock, Smclk};
+use crate::gpio::{Alternate2, Input, Output, Unlocked, P1_6, P1_7};
+use crate::regs::Regs;
+use crate::time::{Hertz, Microseconds};
+use core::convert::{TryFrom, TryInto};
+use core::marker::PhantomData;
 use msp430fr2355 as pac;
 use pac::tb0::tb0ctl::TBSSEL_A;
 use pac::TB0;
 
-pub struct TimerConfig {
+// Capture/compare register markers
+pub struct CC1;
+pub struct CC2;
+
+// Pins that can carry a capture/compare output or input of a timer. PxDIR picks between the two.
+pub trait TimerOutPin<TIMER, CC> {}
+pub trait CapturePin<TIMER, CC> {}
+
+impl<TOK> TimerOutPin<TB0, CC1> for P1_6<Alternate2<Output<TOK>>, Unlocked> {}
+impl<TOK> TimerOutPin<TB0, CC2> for P1_7<Alternate2<Output<TOK>>, Unlocked> {}
+impl<PULL, INTR> CapturePin<TB0, CC1> for P1_6<Alternate2<Input<PULL, INTR>>, Unlocked> {}
+impl<PULL, INTR> CapturePin<TB0, CC2> for P1_7<Alternate2<Input<PULL, INTR>>, Unlocked> {}
+
+// Holds on to the clock it runs from, so the clocks can't be reconfigured under the timer
+pub struct TimerConfig<'c> {
     periph: TB0,
     clk_src: TBSSEL_A,
+    // Unknown for the external clocks
+    clk_freq: Option<Hertz>,
     div: u8,
     div_ex: u8,
+    _clk: PhantomData<&'c ()>,
 }
 
-impl TimerConfig {
-    pub fn use_aclk(mut self, _clk: &Aclk) -> Self {
-        self.clk_src = TBSSEL_A::ACLK;
-        self
+impl<'c> TimerConfig<'c> {
+    fn with_clk<'a>(self, clk_src: TBSSEL_A, clk_freq: Option<Hertz>) -> TimerConfig<'a> {
+        TimerConfig {
+            periph: self.periph,
+            clk_src,
+            clk_freq,
+            div: self.div,
+            div_ex: self.div_ex,
+            _clk: PhantomData,
+        }
     }
 
-    pub fn use_smclk(mut self, _clk: &Smclk) -> Self {
-        self.clk_src = TBSSEL_A::SMCLK;
-        self
+    pub fn use_aclk(self, clk: &Aclk) -> TimerConfig<'_> {
+        self.with_clk(TBSSEL_A::ACLK, Some(clk.freq()))
+    }
+
+    pub fn use_smclk(self, clk: &Smclk) -> TimerConfig<'_> {
+        self.with_clk(TBSSEL_A::SMCLK, Some(clk.freq()))
     }
 
     pub fn use_inclk(mut self) -> Self {
         self.clk_src = TBSSEL_A::INCLK;
+        self.clk_freq = None;
         self
     }
 
     pub fn use_tbclk(mut self) -> Self {
         self.clk_src = TBSSEL_A::TBCLK;
+        self.clk_freq = None;
         self
     }
 
@@ -41,39 +74,67 @@ impl TimerConfig {
         self
     }
 
+    fn tick_rate(&self) -> TickRate<'c> {
+        TickRate {
+            clk_freq: self.clk_freq,
+            div: (1 << self.div) * (self.div_ex as u32 + 1),
+            _clk: PhantomData,
+        }
+    }
+
     fn write_regs(&self) {
-        self.periph.tb0ctl.write(|w| w.tbclr().set_bit());
-        self.periph.tb0ex0.write(|w| w.tbidex().bits(self.div_ex));
+        self.periph.regs().tb0ctl.write(|w| w.tbclr().set_bit());
+        self.periph
+            .regs()
+            .tb0ex0
+            .write(|w| w.tbidex().bits(self.div_ex));
         self.periph
+            .regs()
             .tb0ctl
             .write(|w| w.tbssel().variant(self.clk_src).id().bits(self.div));
     }
 
-    pub fn to_periodic(self) -> TimerParts {
+    pub fn to_periodic(self) -> TimerParts<'c> {
         self.write_regs();
 
+        let rate = self.tick_rate();
         TimerParts {
-            timer: Timer(()),
-            sub_timer1: SubTimer1(()),
-            sub_timer2: SubTimer2(()),
+            timer: Timer(rate),
+            sub_timer1: SubTimer1(rate),
+            sub_timer2: SubTimer2(rate),
         }
     }
 
-    pub fn to_pwm(self) -> Pwms {
+    pub fn to_pwm<PIN1: TimerOutPin<TB0, CC1>, PIN2: TimerOutPin<TB0, CC2>>(
+        self,
+        _pwm1: PIN1,
+        _pwm2: PIN2,
+    ) -> Pwms<'c> {
         self.write_regs();
         // out0 set to toggle, acts as PWM with 50% duty cycle and double the nominal period
-        self.periph.tb0cctl1.write(|w| w.outmod().bits(0b100));
+        self.periph
+            .regs()
+            .tb0cctl1
+            .write(|w| w.outmod().bits(0b100));
         // out1 and out2 set to reset/set acts as normal PWM
-        self.periph.tb0cctl1.write(|w| w.outmod().bits(0b111));
-        self.periph.tb0cctl2.write(|w| w.outmod().bits(0b111));
+        self.periph
+            .regs()
+            .tb0cctl1
+            .write(|w| w.outmod().bits(0b111));
+        self.periph
+            .regs()
+            .tb0cctl2
+            .write(|w| w.outmod().bits(0b111));
 
+        let rate = self.tick_rate();
         Pwms {
-            pwm1: Pwm1(()),
-            pwm2: Pwm2(()),
+            rate,
+            pwm1: Pwm1(rate),
+            pwm2: Pwm2(rate),
         }
     }
 
-    pub fn config_capture(self) -> CaptureConfig {
+    pub fn config_capture(self) -> CaptureConfig<'c> {
         CaptureConfig {
             timer_config: self,
             capture0: CapChannelConfig {
@@ -111,35 +172,74 @@ pub enum TimerDivEx {
 }
 
 pub trait TimerExt {
-    fn constrain(self) -> TimerConfig;
+    fn constrain(self) -> TimerConfig<'static>;
 }
 
 impl TimerExt for TB0 {
-    fn constrain(self) -> TimerConfig {
+    fn constrain(self) -> TimerConfig<'static> {
         TimerConfig {
             periph: self,
             clk_src: TBSSEL_A::TBCLK,
+            clk_freq: None,
             div: 0,
             div_ex: 0,
+            _clk: PhantomData,
         }
     }
 }
 
-pub struct TimerParts {
-    pub timer: Timer,
-    pub sub_timer1: SubTimer1,
-    pub sub_timer2: SubTimer2,
+pub struct TimerParts<'c> {
+    pub timer: Timer<'c>,
+    pub sub_timer1: SubTimer1<'c>,
+    pub sub_timer2: SubTimer2<'c>,
 }
 
-pub struct Timer(());
-pub struct SubTimer1(());
-pub struct SubTimer2(());
+// Counter frequency, kept as the input clock and the total divider so tick counts can be worked
+// out without rounding the divided frequency first
+#[derive(Clone, Copy)]
+struct TickRate<'c> {
+    clk_freq: Option<Hertz>,
+    div: u32,
+    _clk: PhantomData<&'c ()>,
+}
+
+impl TickRate<'_> {
+    // None if the input clock is external or the duration doesn't fit in the counter
+    fn ticks(self, dur: Microseconds) -> Option<u16> {
+        let ticks = self.clk_freq?.ticks_divided(dur, self.div)?;
+        u16::try_from(ticks).ok()
+    }
+
+    // Counter period that repeats at freq, rounded to the nearest tick. None in the same cases as
+    // ticks, or for 0 Hz.
+    fn period_ticks<F: TryInto<Hertz>>(self, freq: F) -> Option<u16> {
+        let denom = freq.try_into().ok()?.0 as u64 * self.div as u64;
+        if denom == 0 {
+            return None;
+        }
+        let ticks = (self.clk_freq?.0 as u64 + denom / 2) / denom;
+        u16::try_from(ticks).ok()
+    }
+}
+
+// A time or frequency that can't be set as a count, either because the timer runs from an external
+// clock of unknown frequency or because the count doesn't fit in 16 bits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutOfRange;
+
+pub struct Timer<'c>(TickRate<'c>);
+pub struct SubTimer1<'c>(TickRate<'c>);
+pub struct SubTimer2<'c>(TickRate<'c>);
 
 // Touches tbccr0, tbctl
-impl Timer {
+impl Timer<'_> {
+    pub fn ticks<D: TryInto<Microseconds>>(&self, dur: D) -> Option<u16> {
+        self.0.ticks(dur.try_into().ok()?)
+    }
+
     // Calling start multiple times without cancelling leads to unreliable behaviour
     pub fn start(&mut self, ticks: u16) {
-        let timer = unsafe { &*TB0::ptr() };
+        let timer = unsafe { TB0::steal() };
         let tbctl = timer.tb0ctl.read();
         if !tbctl.mc().is_stop() {
             timer
@@ -158,9 +258,22 @@ impl Timer {
         timer.tb0ccr0.write(|w| unsafe { w.bits(ticks) });
     }
 
+    pub fn start_time<D: TryInto<Microseconds>>(&mut self, period: D) -> Result<(), OutOfRange> {
+        let ticks = self.ticks(period).ok_or(OutOfRange)?;
+        self.start(ticks);
+        Ok(())
+    }
+
+    // Starts the timer with a period that repeats at freq
+    pub fn start_freq<F: TryInto<Hertz>>(&mut self, freq: F) -> Result<(), OutOfRange> {
+        let ticks = self.0.period_ticks(freq).ok_or(OutOfRange)?;
+        self.start(ticks);
+        Ok(())
+    }
+
     // Always None if called before timer has started
     pub fn wait(&mut self) -> Option<()> {
-        let timer = unsafe { &*TB0::ptr() };
+        let timer = unsafe { TB0::steal() };
         let tbctl = timer.tb0ctl.read();
         if tbctl.tbifg().bit() {
             timer
@@ -172,8 +285,9 @@ impl Timer {
         }
     }
 
+    #[allow(clippy::result_unit_err)]
     pub fn cancel(&mut self) -> Result<(), ()> {
-        let timer = unsafe { &*TB0::ptr() };
+        let timer = unsafe { TB0::steal() };
         let tbctl = timer.tb0ctl.read();
         if tbctl.mc().is_stop() {
             Err(())
@@ -187,17 +301,27 @@ impl Timer {
 }
 
 // Touches tbccr1, tbcctl1
-impl SubTimer1 {
+impl SubTimer1<'_> {
+    pub fn ticks<D: TryInto<Microseconds>>(&self, dur: D) -> Option<u16> {
+        self.0.ticks(dur.try_into().ok()?)
+    }
+
     pub fn set_count(&mut self, ticks: u16) {
-        let timer = unsafe { &*TB0::ptr() };
+        let timer = unsafe { TB0::steal() };
         timer.tb0ccr1.write(|w| unsafe { w.bits(ticks) });
         timer
             .tb0cctl1
             .modify(|r, w| unsafe { w.bits(r.bits()) }.ccifg().clear_bit());
     }
 
+    pub fn set_count_time<D: TryInto<Microseconds>>(&mut self, delay: D) -> Result<(), OutOfRange> {
+        let ticks = self.ticks(delay).ok_or(OutOfRange)?;
+        self.set_count(ticks);
+        Ok(())
+    }
+
     pub fn wait(&mut self) -> Option<()> {
-        let timer = unsafe { &*TB0::ptr() };
+        let timer = unsafe { TB0::steal() };
         let cctl = timer.tb0cctl1.read();
         if cctl.ccifg().bit() {
             timer
@@ -211,17 +335,27 @@ impl SubTimer1 {
 }
 
 // Touches tbccr2, tbcctl2
-impl SubTimer2 {
+impl SubTimer2<'_> {
+    pub fn ticks<D: TryInto<Microseconds>>(&self, dur: D) -> Option<u16> {
+        self.0.ticks(dur.try_into().ok()?)
+    }
+
     pub fn set_count(&mut self, ticks: u16) {
-        let timer = unsafe { &*TB0::ptr() };
+        let timer = unsafe { TB0::steal() };
         timer.tb0ccr2.write(|w| unsafe { w.bits(ticks) });
         timer
             .tb0cctl2
             .modify(|r, w| unsafe { w.bits(r.bits()) }.ccifg().clear_bit());
     }
 
+    pub fn set_count_time<D: TryInto<Microseconds>>(&mut self, delay: D) -> Result<(), OutOfRange> {
+        let ticks = self.ticks(delay).ok_or(OutOfRange)?;
+        self.set_count(ticks);
+        Ok(())
+    }
+
     pub fn wait(&mut self) -> Option<()> {
-        let timer = unsafe { &*TB0::ptr() };
+        let timer = unsafe { TB0::steal() };
         let cctl = timer.tb0cctl2.read();
         if cctl.ccifg().bit() {
             timer
@@ -234,19 +368,39 @@ impl SubTimer2 {
     }
 }
 
-pub struct Pwms {
-    pub pwm1: Pwm1,
-    pub pwm2: Pwm2,
+pub struct Pwms<'c> {
+    rate: TickRate<'c>,
+    pub pwm1: Pwm1<'c>,
+    pub pwm2: Pwm2<'c>,
 }
 
-impl Pwms {
+impl Pwms<'_> {
+    pub fn ticks<D: TryInto<Microseconds>>(&self, dur: D) -> Option<u16> {
+        self.rate.ticks(dur.try_into().ok()?)
+    }
+
     pub fn set_period(&mut self, ticks: u16) {
-        let timer = unsafe { &*TB0::ptr() };
+        let timer = unsafe { TB0::steal() };
         timer.tb0ccr0.write(|w| unsafe { w.bits(ticks) });
     }
 
+    pub fn set_period_time<D: TryInto<Microseconds>>(
+        &mut self,
+        period: D,
+    ) -> Result<(), OutOfRange> {
+        let ticks = self.ticks(period).ok_or(OutOfRange)?;
+        self.set_period(ticks);
+        Ok(())
+    }
+
+    pub fn set_period_freq<F: TryInto<Hertz>>(&mut self, freq: F) -> Result<(), OutOfRange> {
+        let ticks = self.rate.period_ticks(freq).ok_or(OutOfRange)?;
+        self.set_period(ticks);
+        Ok(())
+    }
+
     pub fn enable(&mut self) {
-        let timer = unsafe { &*TB0::ptr() };
+        let timer = unsafe { TB0::steal() };
         timer.tb0ctl.modify(|r, w| {
             unsafe { w.bits(r.bits()) }
                 .tbclr()
@@ -259,33 +413,53 @@ impl Pwms {
     }
 
     pub fn disable(&mut self) {
-        let timer = unsafe { &*TB0::ptr() };
+        let timer = unsafe { TB0::steal() };
         timer
             .tb0ctl
             .modify(|r, w| unsafe { w.bits(r.bits()) }.mc().stop());
     }
 }
 
-pub struct Pwm1(());
-pub struct Pwm2(());
+pub struct Pwm1<'c>(TickRate<'c>);
+pub struct Pwm2<'c>(TickRate<'c>);
 
 // If duty > period, output signal stays high
-impl Pwm1 {
+impl Pwm1<'_> {
+    pub fn ticks<D: TryInto<Microseconds>>(&self, dur: D) -> Option<u16> {
+        self.0.ticks(dur.try_into().ok()?)
+    }
+
     pub fn set_duty(&mut self, ticks: u16) {
-        let timer = unsafe { &*TB0::ptr() };
+        let timer = unsafe { TB0::steal() };
         timer.tb0ccr1.write(|w| unsafe { w.bits(ticks) });
     }
+
+    pub fn set_duty_time<D: TryInto<Microseconds>>(&mut self, high: D) -> Result<(), OutOfRange> {
+        let ticks = self.ticks(high).ok_or(OutOfRange)?;
+        self.set_duty(ticks);
+        Ok(())
+    }
 }
 
-impl Pwm2 {
+impl Pwm2<'_> {
+    pub fn ticks<D: TryInto<Microseconds>>(&self, dur: D) -> Option<u16> {
+        self.0.ticks(dur.try_into().ok()?)
+    }
+
     pub fn set_duty(&mut self, ticks: u16) {
-        let timer = unsafe { &*TB0::ptr() };
+        let timer = unsafe { TB0::steal() };
         timer.tb0ccr2.write(|w| unsafe { w.bits(ticks) });
     }
+
+    pub fn set_duty_time<D: TryInto<Microseconds>>(&mut self, high: D) -> Result<(), OutOfRange> {
+        let ticks = self.ticks(high).ok_or(OutOfRange)?;
+        self.set_duty(ticks);
+        Ok(())
+    }
 }
 
-pub struct CaptureConfig {
-    timer_config: TimerConfig,
+pub struct CaptureConfig<'c> {
+    timer_config: TimerConfig<'c>,
     capture0: CapChannelConfig,
     capture1: CapChannelConfig,
     capture2: CapChannelConfig,
@@ -312,7 +486,7 @@ pub enum CaptureSelect {
     Vcc,
 }
 
-impl CaptureConfig {
+impl<'c> CaptureConfig<'c> {
     pub fn config_chan0(mut self, cap_mode: CaptureMode, select: CaptureSelect) -> Self {
         self.capture0 = CapChannelConfig { cap_mode, select };
         self
@@ -328,9 +502,26 @@ impl CaptureConfig {
         self
     }
 
-    pub fn freeze(self) -> Capture {
+    // Captures from the CCIxA pin of the channel
+    pub fn config_chan1_pin<PIN: CapturePin<TB0, CC1>>(
+        self,
+        cap_mode: CaptureMode,
+        _pin: PIN,
+    ) -> Self {
+        self.config_chan1(cap_mode, CaptureSelect::CapInputA)
+    }
+
+    pub fn config_chan2_pin<PIN: CapturePin<TB0, CC2>>(
+        self,
+        cap_mode: CaptureMode,
+        _pin: PIN,
+    ) -> Self {
+        self.config_chan2(cap_mode, CaptureSelect::CapInputA)
+    }
+
+    pub fn freeze(self) -> Capture<'c> {
         self.timer_config.write_regs();
-        self.timer_config.periph.tb0cctl0.write(|w| {
+        self.timer_config.periph.regs().tb0cctl0.write(|w| {
             w.cap()
                 .capture()
                 .scs()
@@ -341,7 +532,7 @@ impl CaptureConfig {
                 .bits(self.capture0.select as u8)
         });
 
-        self.timer_config.periph.tb0cctl1.write(|w| {
+        self.timer_config.periph.regs().tb0cctl1.write(|w| {
             w.cap()
                 .capture()
                 .scs()
@@ -352,7 +543,7 @@ impl CaptureConfig {
                 .bits(self.capture1.select as u8)
         });
 
-        self.timer_config.periph.tb0cctl2.write(|w| {
+        self.timer_config.periph.regs().tb0cctl2.write(|w| {
             w.cap()
                 .capture()
                 .scs()
@@ -363,7 +554,7 @@ impl CaptureConfig {
                 .bits(self.capture2.select as u8)
         });
 
-        self.timer_config.periph.tb0ctl.modify(|r, w| {
+        self.timer_config.periph.regs().tb0ctl.modify(|r, w| {
             unsafe { w.bits(r.bits()) }
                 .tbclr()
                 .set_bit()
@@ -375,14 +566,16 @@ impl CaptureConfig {
             capture0: CaptureChannnel0(()),
             capture1: CaptureChannnel1(()),
             capture2: CaptureChannnel2(()),
+            _clk: PhantomData,
         }
     }
 }
 
-pub struct Capture {
+pub struct Capture<'c> {
     pub capture0: CaptureChannnel0,
     pub capture1: CaptureChannnel1,
     pub capture2: CaptureChannnel2,
+    _clk: PhantomData<&'c ()>,
 }
 
 pub struct CaptureChannnel0(());
@@ -391,7 +584,7 @@ pub struct CaptureChannnel2(());
 
 impl CaptureChannnel1 {
     fn clear(&mut self, cctl: u16) {
-        let timer = unsafe { &*TB0::ptr() };
+        let timer = unsafe { TB0::steal() };
         timer.tb0cctl1.write(|w| {
             unsafe { w.bits(cctl) }
                 .ccifg()
@@ -402,7 +595,7 @@ impl CaptureChannnel1 {
     }
 
     pub fn capture(&mut self) -> Result<Option<u16>, u16> {
-        let timer = unsafe { &*TB0::ptr() };
+        let timer = unsafe { TB0::steal() };
         let cctl = timer.tb0cctl1.read();
         if cctl.cov().bit() {
             self.clear(cctl.bits());
@@ -422,3 +615,154 @@ impl CaptureChannnel1 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clocks::{CsExt, FramExt};
+    use crate::gpio::{GpioExt, PmmExt};
+    use crate::regs::mock;
+    use crate::time::U32Ext;
+
+    fn tb0() -> &'static pac::tb0::RegisterBlock {
+        unsafe { TB0::steal() }
+    }
+
+    #[test]
+    fn pwm() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let pmm = periph.PMM.freeze();
+        let p1 = periph.P1.constrain().unlock(&pmm).split();
+        let pwm1 = p1.p1_6.to_output(&p1.pdir).alternate2(&p1.psel);
+        let pwm2 = p1.p1_7.to_output(&p1.pdir).alternate2(&p1.psel);
+
+        let mut pwms = periph
+            .TB0
+            .constrain()
+            .set_div(TimerDiv::_4)
+            .to_pwm(pwm1, pwm2);
+        assert!(tb0().tb0ctl.read().tbssel().is_tbclk());
+        assert_eq!(tb0().tb0ctl.read().id().bits(), 2);
+        assert_eq!(tb0().tb0cctl1.read().outmod().bits(), 0b111);
+        assert_eq!(tb0().tb0cctl2.read().outmod().bits(), 0b111);
+
+        pwms.set_period(1000);
+        pwms.pwm1.set_duty(100);
+        pwms.pwm2.set_duty(795);
+        pwms.enable();
+        assert_eq!(tb0().tb0ccr0.read().bits(), 1000);
+        assert_eq!(tb0().tb0ccr1.read().bits(), 100);
+        assert_eq!(tb0().tb0ccr2.read().bits(), 795);
+        assert!(tb0().tb0ctl.read().mc().is_up());
+
+        pwms.disable();
+        assert!(tb0().tb0ctl.read().mc().is_stop());
+    }
+
+    #[test]
+    fn periodic() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut parts = periph.TB0.constrain().to_periodic();
+
+        parts.timer.start(500);
+        assert_eq!(tb0().tb0ccr0.read().bits(), 500);
+        assert!(tb0().tb0ctl.read().mc().is_up());
+
+        assert!(parts.timer.wait().is_none());
+        tb0().tb0ctl.modify(|_, w| w.tbifg().set_bit());
+        assert!(parts.timer.wait().is_some());
+        assert!(tb0().tb0ctl.read().tbifg().bit_is_clear());
+
+        assert!(parts.timer.cancel().is_ok());
+        assert!(parts.timer.cancel().is_err());
+
+        parts.sub_timer1.set_count(200);
+        assert_eq!(tb0().tb0ccr1.read().bits(), 200);
+    }
+
+    #[test]
+    fn ticks_from_duration() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut fram = periph.FRCTL.constrain();
+        let (_mclk, _smclk, aclk) = periph
+            .CS
+            .constrain()
+            .mclk_dcoclk(1.mhz())
+            .unwrap()
+            .smclk_divide_1()
+            .aclk_refoclk()
+            .freeze(&mut fram);
+        let mut parts = periph
+            .TB0
+            .constrain()
+            .use_aclk(&aclk)
+            .set_div(TimerDiv::_2)
+            .set_div_ex(TimerDivEx::_3)
+            .to_periodic();
+
+        // 32768 Hz / 6 is 5461.33 ticks a second
+        assert_eq!(parts.timer.ticks(1_000.ms()), Some(5461));
+        assert_eq!(parts.sub_timer1.ticks(3.ms()), Some(16));
+        assert_eq!(parts.timer.ticks(20_000.ms()), None);
+        parts.timer.start(parts.timer.ticks(100.ms()).unwrap());
+        assert_eq!(tb0().tb0ccr0.read().bits(), 546);
+
+        parts.timer.start_time(200.ms()).unwrap();
+        assert_eq!(tb0().tb0ccr0.read().bits(), 1092);
+        parts.timer.start_freq(10.hz()).unwrap();
+        assert_eq!(tb0().tb0ccr0.read().bits(), 546);
+        parts.sub_timer1.set_count_time(3_000.us()).unwrap();
+        assert_eq!(tb0().tb0ccr1.read().bits(), 16);
+        assert_eq!(parts.timer.start_time(20_000.ms()), Err(OutOfRange));
+        assert_eq!(parts.timer.start_freq(0.hz()), Err(OutOfRange));
+        assert_eq!(parts.timer.start_freq(5_000.mhz()), Err(OutOfRange));
+        // 1 Hz is a whole second of ticks, which still fits
+        assert_eq!(parts.timer.start_freq(1.hz()), Ok(()));
+        assert_eq!(tb0().tb0ccr0.read().bits(), 5461);
+        assert_eq!(
+            parts.sub_timer2.set_count_time(u32::MAX.ms()),
+            Err(OutOfRange)
+        );
+
+        // Nothing is known about the frequency of the external clocks
+        let mut parts = unsafe { pac::Peripherals::steal() }
+            .TB0
+            .constrain()
+            .use_tbclk()
+            .to_periodic();
+        assert_eq!(parts.timer.ticks(1.ms()), None);
+        assert_eq!(parts.timer.start_freq(1.khz()), Err(OutOfRange));
+    }
+
+    #[test]
+    fn pwm_from_time() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let pmm = periph.PMM.freeze();
+        let p1 = periph.P1.constrain().unlock(&pmm).split();
+        let pwm1 = p1.p1_6.to_output(&p1.pdir).alternate2(&p1.psel);
+        let pwm2 = p1.p1_7.to_output(&p1.pdir).alternate2(&p1.psel);
+        let mut fram = periph.FRCTL.constrain();
+        let (_mclk, smclk, _aclk) = periph
+            .CS
+            .constrain()
+            .mclk_dcoclk(1.mhz())
+            .unwrap()
+            .smclk_divide_1()
+            .freeze(&mut fram);
+
+        // SMCLK ends up at 31 * 32768 Hz
+        let mut pwms = periph.TB0.constrain().use_smclk(&smclk).to_pwm(pwm1, pwm2);
+        pwms.set_period_freq(1.khz()).unwrap();
+        assert_eq!(tb0().tb0ccr0.read().bits(), 1016);
+        pwms.set_period_time(2.ms()).unwrap();
+        assert_eq!(tb0().tb0ccr0.read().bits(), 2032);
+        pwms.pwm1.set_duty_time(500.us()).unwrap();
+        assert_eq!(tb0().tb0ccr1.read().bits(), 508);
+        assert_eq!(pwms.pwm2.set_duty_time(1_000.ms()), Err(OutOfRange));
+        assert_eq!(pwms.set_period_freq(10.hz()), Err(OutOfRange));
+    }
+}
@@ -1,4 +1,7 @@
-use crate::clocks::{Aclk, Smclk};
+use crate::clocks::{Aclk, Clock, Smclk, VLOCLK};
+use crate::regs::Regs;
+use crate::time::{Hertz, Microseconds};
+use core::convert::TryInto;
 use core::marker::PhantomData;
 use msp430fr2355 as pac;
 use pac::wdt_a::wdtctl::WDTSSEL_A;
@@ -6,24 +9,31 @@ use pac::wdt_a::wdtctl::WDTSSEL_A;
 pub use pac::wdt_a::wdtctl::WDTIS_A as WdtClkPeriods;
 
 pub trait WdtExt {
-    fn constrain(self) -> Wdt<WatchdogMode>;
+    fn constrain(self) -> Wdt<'static, WatchdogMode>;
 }
 
 impl WdtExt for pac::WDT_A {
-    fn constrain(self) -> Wdt<WatchdogMode> {
+    fn constrain(self) -> Wdt<'static, WatchdogMode> {
         // Disable first
-        self.wdtctl
+        self.regs()
+            .wdtctl
             .write(|w| unsafe { w.wdtpw().bits(0x5A) }.wdthold().hold());
         Wdt {
             _mode: PhantomData,
+            clk_freq: None,
             periph: self,
+            _clk: PhantomData,
         }
     }
 }
 
-pub struct Wdt<MODE> {
+// Holds on to the clock it runs from, so the clocks can't be reconfigured under the timeout math
+pub struct Wdt<'c, MODE> {
     _mode: PhantomData<MODE>,
+    // Unknown until a clock is picked
+    clk_freq: Option<Hertz>,
     periph: pac::WDT_A,
+    _clk: PhantomData<&'c ()>,
 }
 
 pub struct WatchdogMode;
@@ -31,38 +41,66 @@ pub struct IntervalMode;
 
 const PASSWORD: u8 = 0x5A;
 
-impl<MODE> Wdt<MODE> {
-    fn set_clk(self, clk_src: WDTSSEL_A) -> Self {
-        let bits = self.periph.wdtctl.read().bits();
+// Intervals from shortest to longest, in clock cycles
+const PERIODS: [(u32, WdtClkPeriods); 8] = [
+    (1 << 6, WdtClkPeriods::_64),
+    (1 << 9, WdtClkPeriods::_512),
+    (1 << 13, WdtClkPeriods::_8192),
+    (1 << 15, WdtClkPeriods::_32K),
+    (1 << 19, WdtClkPeriods::_512K),
+    (1 << 23, WdtClkPeriods::_8192K),
+    (1 << 27, WdtClkPeriods::_128M),
+    (1 << 31, WdtClkPeriods::_2G),
+];
+
+impl<'c, MODE> Wdt<'c, MODE> {
+    fn set_clk<'a>(self, clk_src: WDTSSEL_A, clk_freq: Hertz) -> Wdt<'a, MODE> {
+        let bits = self.periph.regs().wdtctl.read().bits();
         // Halt timer first
-        self.periph.wdtctl.write(|w| {
+        self.periph.regs().wdtctl.write(|w| {
             unsafe { w.bits(bits).wdtpw().bits(PASSWORD) }
                 .wdthold()
                 .hold()
         });
         // Set clock src
-        self.periph.wdtctl.write(|w| {
+        self.periph.regs().wdtctl.write(|w| {
             unsafe { w.bits(bits).wdtpw().bits(PASSWORD) }
                 .wdtssel()
                 .variant(clk_src)
         });
-        self
+        Wdt {
+            _mode: PhantomData,
+            clk_freq: Some(clk_freq),
+            periph: self.periph,
+            _clk: PhantomData,
+        }
+    }
+
+    pub fn set_aclk(self, clks: &Aclk) -> Wdt<'_, MODE> {
+        self.set_clk(WDTSSEL_A::ACLK, clks.freq())
     }
 
-    pub fn set_aclk(self, _clks: &Aclk) -> Self {
-        self.set_clk(WDTSSEL_A::ACLK)
+    // VLO isn't part of the clock system, so nothing stays borrowed
+    pub fn set_vloclk(self) -> Wdt<'static, MODE> {
+        self.set_clk(WDTSSEL_A::VLOCLK, Hertz(VLOCLK as u32))
     }
 
-    pub fn set_vloclk(self) -> Self {
-        self.set_clk(WDTSSEL_A::VLOCLK)
+    pub fn set_smclk(self, clks: &Smclk) -> Wdt<'_, MODE> {
+        self.set_clk(WDTSSEL_A::SMCLK, clks.freq())
     }
 
-    pub fn set_smclk(self, _clks: &Smclk) -> Self {
-        self.set_clk(WDTSSEL_A::SMCLK)
+    // Shortest interval that lasts at least as long as the timeout. None if no clock has been
+    // set or the timeout is longer than the longest interval.
+    pub fn periods<D: TryInto<Microseconds>>(&self, timeout: D) -> Option<WdtClkPeriods> {
+        let ticks = self.clk_freq?.ticks(timeout)?;
+        PERIODS
+            .iter()
+            .find(|(cycles, _)| *cycles >= ticks)
+            .map(|&(_, periods)| periods)
     }
 
     pub fn reset(&mut self) {
-        self.periph.wdtctl.modify(|r, w| {
+        self.periph.regs().wdtctl.modify(|r, w| {
             unsafe { w.bits(r.bits()).wdtpw().bits(PASSWORD) }
                 .wdtcntcl()
                 .set_bit()
@@ -70,7 +108,7 @@ impl<MODE> Wdt<MODE> {
     }
 
     pub fn disable(&mut self) {
-        self.periph.wdtctl.modify(|r, w| {
+        self.periph.regs().wdtctl.modify(|r, w| {
             unsafe { w.bits(r.bits()).wdtpw().bits(PASSWORD) }
                 .wdthold()
                 .hold()
@@ -78,7 +116,7 @@ impl<MODE> Wdt<MODE> {
     }
 
     pub fn start(&mut self, periods: WdtClkPeriods) {
-        self.periph.wdtctl.modify(|r, w| {
+        self.periph.regs().wdtctl.modify(|r, w| {
             unsafe { w.bits(r.bits()).wdtpw().bits(PASSWORD) }
                 // Reset countdown
                 .wdtcntcl()
@@ -94,7 +132,7 @@ impl<MODE> Wdt<MODE> {
 
     // Don't call this unless type state is also changing
     fn change_mode(&mut self, mode: bool) {
-        self.periph.wdtctl.modify(|r, w| {
+        self.periph.regs().wdtctl.modify(|r, w| {
             unsafe { w.bits(r.bits()).wdtpw().bits(PASSWORD) }
                 // Pause timer when switching modes
                 .wdthold()
@@ -105,30 +143,34 @@ impl<MODE> Wdt<MODE> {
     }
 }
 
-impl Wdt<WatchdogMode> {
-    pub fn to_interval(mut self) -> Wdt<IntervalMode> {
-        unsafe { &*pac::SFR::ptr() }
+impl<'c> Wdt<'c, WatchdogMode> {
+    pub fn to_interval(mut self) -> Wdt<'c, IntervalMode> {
+        unsafe { pac::SFR::steal() }
             .sfrifg1
             .write(|w| w.wdtifg().clear_bit());
         self.change_mode(true);
         Wdt {
             _mode: PhantomData,
+            clk_freq: self.clk_freq,
             periph: self.periph,
+            _clk: PhantomData,
         }
     }
 }
 
-impl Wdt<IntervalMode> {
-    pub fn to_watchdog(mut self) -> Wdt<WatchdogMode> {
+impl<'c> Wdt<'c, IntervalMode> {
+    pub fn to_watchdog(mut self) -> Wdt<'c, WatchdogMode> {
         self.change_mode(false);
         Wdt {
             _mode: PhantomData,
+            clk_freq: self.clk_freq,
             periph: self.periph,
+            _clk: PhantomData,
         }
     }
 
     pub fn wait_done(&mut self) -> bool {
-        let sfr = unsafe { &*pac::SFR::ptr() };
+        let sfr = unsafe { pac::SFR::steal() };
         if sfr.sfrifg1.read().wdtifg().is_wdtifg_1() {
             sfr.sfrifg1.write(|w| w.wdtifg().clear_bit());
             true
@@ -137,3 +179,64 @@ impl Wdt<IntervalMode> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regs::mock;
+    use crate::time::U32Ext;
+
+    fn wdtctl() -> pac::wdt_a::wdtctl::R {
+        unsafe { pac::WDT_A::steal() }.wdtctl.read()
+    }
+
+    #[test]
+    fn constrain_holds() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        periph.WDT_A.constrain();
+        assert!(wdtctl().wdthold().is_hold());
+        assert_eq!(wdtctl().wdtpw().bits(), PASSWORD);
+    }
+
+    #[test]
+    fn start_and_mode() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let mut wdt = periph.WDT_A.constrain().set_vloclk();
+        assert!(wdtctl().wdtssel().is_vloclk());
+
+        wdt.start(WdtClkPeriods::_8192);
+        assert!(wdtctl().wdthold().is_unhold());
+        assert!(wdtctl().wdtis().is_8192());
+        assert!(wdtctl().wdtssel().is_vloclk());
+
+        let mut wdt = wdt.to_interval();
+        assert!(wdtctl().wdthold().is_hold());
+        assert!(wdtctl().wdttmsel().bit_is_set());
+
+        assert!(!wdt.wait_done());
+        unsafe { pac::SFR::steal() }
+            .sfrifg1
+            .modify(|_, w| w.wdtifg().set_bit());
+        assert!(wdt.wait_done());
+    }
+
+    #[test]
+    fn periods_from_timeout() {
+        mock::reset();
+        let periph = unsafe { pac::Peripherals::steal() };
+        let wdt = periph.WDT_A.constrain();
+        assert!(wdt.periods(1.ms()).is_none());
+
+        // 10 kHz VLO: 8192 cycles is 819.2 ms, 32768 cycles is 3.28 s
+        let wdt = wdt.set_vloclk();
+        assert!(matches!(wdt.periods(500.ms()), Some(WdtClkPeriods::_8192)));
+        assert!(matches!(wdt.periods(820.ms()), Some(WdtClkPeriods::_32K)));
+        assert!(matches!(wdt.periods(1.us()), Some(WdtClkPeriods::_64)));
+        assert!(matches!(
+            wdt.to_interval().periods(4_000_000.ms()),
+            Some(WdtClkPeriods::_128M)
+        ));
+    }
+}